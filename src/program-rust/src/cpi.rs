@@ -0,0 +1,457 @@
+//! Helpers for other on-chain programs calling the staking program.
+//! Add this crate with `features = ["no-entrypoint", "client"]` and pass the
+//! seeds of your PDA in `signer_seeds` to act on its behalf, or `&[]` if the
+//! holder signed the outer transaction.
+//! Withdraw helpers support pools without a reward vault, for the others build the
+//! instruction with `instruction::add_reward_accounts` and invoke it directly.
+
+use crate::instruction;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, program::invoke_signed, pubkey::Pubkey};
+
+/// `payer` must be a signer with enough lamports for the rent of the new staking account.
+#[allow(clippy::too_many_arguments)]
+pub fn stake<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    duration: u16,
+    amount: u64,
+    position_index: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::stake(
+        staking_program.key,
+        payer.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+        duration,
+        amount,
+        position_index,
+    );
+    invoke_signed(
+        &ix,
+        &[
+            staking_account.clone(),
+            owner.clone(),
+            owner_token_account.clone(),
+            pool_token_account.clone(),
+            token_program.clone(),
+            pool_config.clone(),
+            payer.clone(),
+            system_program.clone(),
+            staking_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn add_stake<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::add_stake(
+        staking_program.key,
+        staking_account.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+        amount,
+    );
+    invoke_signed(
+        &ix,
+        &[
+            staking_account.clone(),
+            owner.clone(),
+            owner_token_account.clone(),
+            pool_token_account.clone(),
+            token_program.clone(),
+            pool_config.clone(),
+            staking_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_interest<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::withdraw_interest(
+        staking_program.key,
+        staking_account.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+    );
+    invoke_withdraw(
+        &ix,
+        staking_program,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        token_program,
+        pool_manager,
+        pool_config,
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn compound<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::compound(
+        staking_program.key,
+        staking_account.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+    );
+    invoke_withdraw(
+        &ix,
+        staking_program,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        token_program,
+        pool_manager,
+        pool_config,
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_principal<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::withdraw_principal(
+        staking_program.key,
+        staking_account.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+        amount,
+    );
+    invoke_withdraw(
+        &ix,
+        staking_program,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        token_program,
+        pool_manager,
+        pool_config,
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn close_account<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    rent_receiver: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::close_account(
+        staking_program.key,
+        staking_account.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+        rent_receiver.key,
+    );
+    invoke_signed(
+        &ix,
+        &[
+            staking_account.clone(),
+            owner.clone(),
+            owner_token_account.clone(),
+            pool_token_account.clone(),
+            token_program.clone(),
+            pool_manager.clone(),
+            pool_config.clone(),
+            rent_receiver.clone(),
+            staking_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn emergency_exit<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    rent_receiver: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::emergency_exit(
+        staking_program.key,
+        staking_account.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+        rent_receiver.key,
+    );
+    invoke_signed(
+        &ix,
+        &[
+            staking_account.clone(),
+            owner.clone(),
+            owner_token_account.clone(),
+            pool_token_account.clone(),
+            token_program.clone(),
+            pool_manager.clone(),
+            pool_config.clone(),
+            rent_receiver.clone(),
+            staking_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn early_unstake<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    rent_receiver: &AccountInfo<'a>,
+    treasury: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::early_unstake(
+        staking_program.key,
+        staking_account.key,
+        owner.key,
+        owner_token_account.key,
+        pool_token_account.key,
+        rent_receiver.key,
+        treasury.key,
+    );
+    invoke_signed(
+        &ix,
+        &[
+            staking_account.clone(),
+            owner.clone(),
+            owner_token_account.clone(),
+            pool_token_account.clone(),
+            token_program.clone(),
+            pool_manager.clone(),
+            pool_config.clone(),
+            rent_receiver.clone(),
+            treasury.clone(),
+            staking_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn invoke_withdraw<'a>(
+    ix: &solana_program::instruction::Instruction,
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    invoke_signed(
+        ix,
+        &[
+            staking_account.clone(),
+            owner.clone(),
+            owner_token_account.clone(),
+            pool_token_account.clone(),
+            token_program.clone(),
+            pool_manager.clone(),
+            pool_config.clone(),
+            staking_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+pub fn set_auto_renew<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    auto_renew: bool,
+    compound: bool,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::set_auto_renew(staking_program.key, staking_account.key, owner.key, auto_renew, compound);
+    invoke_signed(
+        &ix,
+        &[staking_account.clone(), owner.clone(), pool_config.clone(), staking_program.clone()],
+        signer_seeds,
+    )
+}
+
+pub fn renew<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    duration: u16,
+    compound: bool,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::renew(staking_program.key, staking_account.key, owner.key, duration, compound);
+    invoke_signed(
+        &ix,
+        &[staking_account.clone(), owner.clone(), pool_config.clone(), staking_program.clone()],
+        signer_seeds,
+    )
+}
+
+/// Transfers the position without the new holder's signature.
+pub fn transfer_position<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    new_holder: &Pubkey,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::transfer_position(staking_program.key, staking_account.key, owner.key, new_holder, false);
+    invoke_signed(
+        &ix,
+        &[staking_account.clone(), owner.clone(), pool_config.clone(), staking_program.clone()],
+        signer_seeds,
+    )
+}
+
+pub fn set_delegated_compounding<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    enabled: bool,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::set_delegated_compounding(staking_program.key, staking_account.key, owner.key, enabled);
+    invoke_signed(
+        &ix,
+        &[staking_account.clone(), owner.clone(), pool_config.clone(), staking_program.clone()],
+        signer_seeds,
+    )
+}
+
+/// `stream_accounts` are pairs of (stream vault, holder token account) as in `instruction::claim_rewards`.
+#[allow(clippy::too_many_arguments)]
+pub fn claim_rewards<'a>(
+    staking_program: &AccountInfo<'a>,
+    staking_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    pool_manager: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    stream_accounts: &[(AccountInfo<'a>, AccountInfo<'a>)],
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let streams: Vec<_> = stream_accounts.iter().map(|(vault, owner_token)| (*vault.key, *owner_token.key)).collect();
+    let ix = instruction::claim_rewards(staking_program.key, staking_account.key, owner.key, &streams);
+    let mut account_infos = vec![
+        staking_account.clone(),
+        owner.clone(),
+        token_program.clone(),
+        pool_manager.clone(),
+        pool_config.clone(),
+    ];
+    for (vault, owner_token) in stream_accounts {
+        account_infos.push(vault.clone());
+        account_infos.push(owner_token.clone());
+    }
+    account_infos.push(staking_program.clone());
+    invoke_signed(&ix, &account_infos, signer_seeds)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn fund_rewards<'a>(
+    staking_program: &AccountInfo<'a>,
+    pool_config: &AccountInfo<'a>,
+    funder: &AccountInfo<'a>,
+    funder_token_account: &AccountInfo<'a>,
+    interest_vault: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = instruction::fund_rewards(staking_program.key, funder.key, funder_token_account.key, interest_vault.key, amount);
+    invoke_signed(
+        &ix,
+        &[
+            pool_config.clone(),
+            funder.clone(),
+            funder_token_account.clone(),
+            interest_vault.clone(),
+            token_program.clone(),
+            staking_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
@@ -0,0 +1,135 @@
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
+use thiserror::Error;
+
+/// Errors returned by the staking program as `ProgramError::Custom(code)`.
+/// Codes are part of the public interface, never renumber existing variants, only append.
+#[derive(Clone, Copy, Debug, Eq, Error, FromPrimitive, PartialEq)]
+pub enum StakingError {
+    #[error("Pool is already initialized")]
+    PoolAlreadyInitialized = 0,
+    #[error("Pool is not initialized")]
+    PoolNotInitialized = 1,
+    #[error("Pool config account is not the pool PDA")]
+    InvalidPoolConfig = 2,
+    #[error("Only the pool admin can do this")]
+    NotPoolAdmin = 3,
+    #[error("Tier has zero duration or zero rate denominator")]
+    InvalidTier = 4,
+    #[error("Pool can not have that many tiers")]
+    TooManyTiers = 5,
+    #[error("Tier with this duration already exists")]
+    TierAlreadyExists = 6,
+    #[error("There is no tier with this duration")]
+    TierNotFound = 7,
+    #[error("Tier is retired and closed for new stakes")]
+    TierRetired = 8,
+    #[error("Staking account is already initialized")]
+    StakingAccountAlreadyInitialized = 9,
+    #[error("Staking account is not initialized")]
+    StakingAccountNotInitialized = 10,
+    #[error("Staking account belongs to another holder")]
+    WrongHolder = 11,
+    #[error("Last withdraw date is in the future")]
+    InvalidTimestamp = 12,
+    #[error("Lock period is not over yet")]
+    LockNotExpired = 13,
+    #[error("Arithmetic overflow")]
+    MathOverflow = 14,
+    #[error("Pool token account is not the pool vault")]
+    InvalidPoolVault = 15,
+    #[error("Pool vault is not owned by the pool manager")]
+    VaultNotOwnedByPool = 16,
+    #[error("Token account mint does not match the pool mint")]
+    MintMismatch = 17,
+    #[error("Account is not an initialized token account or mint")]
+    InvalidTokenAccount = 18,
+    #[error("Holder must sign the instruction")]
+    HolderNotSigner = 19,
+    #[error("Account must be writable")]
+    AccountNotWritable = 20,
+    #[error("Token account does not belong to the holder")]
+    TokenAccountNotOwnedByHolder = 21,
+    #[error("Source and destination token accounts are the same")]
+    SelfTransfer = 22,
+    #[error("Pool manager account is not the pool authority PDA")]
+    InvalidPoolAuthority = 23,
+    #[error("Staking account is not the PDA of the holder and position index")]
+    InvalidStakingAccountAddress = 24,
+    #[error("Staking account is closed")]
+    PositionClosed = 25,
+    #[error("Treasury account is not the pool treasury")]
+    InvalidTreasury = 26,
+    #[error("Amount is zero or more than the staked principal")]
+    InsufficientPrincipal = 27,
+    #[error("Holder has not enabled auto renew")]
+    AutoRenewDisabled = 28,
+    #[error("Position can not be transferred to this holder")]
+    InvalidNewHolder = 29,
+    #[error("Holder has not enabled delegated compounding")]
+    DelegatedCompoundingDisabled = 30,
+    #[error("Keeper tip is too high")]
+    InvalidKeeperTip = 31,
+    #[error("Interest is paid in the reward mint and can not be compounded")]
+    CompoundNotSupported = 32,
+    #[error("Pool has no free reward stream slots")]
+    TooManyRewardStreams = 33,
+    #[error("Reward stream parameters are invalid")]
+    InvalidRewardStream = 34,
+    #[error("Reward stream not found")]
+    RewardStreamNotFound = 35,
+    #[error("Reward stream can still be claimed")]
+    RewardStreamActive = 36,
+    #[error("Reward reserve of the pool does not cover the interest")]
+    InsufficientRewardReserve = 37,
+    #[error("Funded rewards do not cover the interest promised to the stakes")]
+    InterestNotCovered = 38,
+    #[error("Instruction is paused by the pool admin")]
+    InstructionPaused = 39,
+    #[error("Emergency exit is available only while interest payouts are paused")]
+    PoolNotPaused = 40,
+    #[error("Only the upgrade authority of the program can initialize the pool")]
+    NotUpgradeAuthority = 41,
+    #[error("Pool with a reward vault needs a treasury for early unstake penalties")]
+    TreasuryRequired = 42,
+}
+
+impl StakingError {
+    /// Client side decoder for the code of `ProgramError::Custom` or `InstructionError::Custom`.
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::from_u32(code)
+    }
+
+    pub fn from_program_error(error: &ProgramError) -> Option<Self> {
+        match error {
+            ProgramError::Custom(code) => Self::from_code(*code),
+            _ => None,
+        }
+    }
+}
+
+impl From<StakingError> for ProgramError {
+    fn from(e: StakingError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for StakingError {
+    fn type_of() -> &'static str {
+        "StakingError"
+    }
+}
+
+impl PrintProgramError for StakingError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        msg!("Error: {}", self);
+    }
+}
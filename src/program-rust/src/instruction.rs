@@ -0,0 +1,543 @@
+//! Instruction builders for clients and tests.
+//! Every builder derives the pool PDAs from `program_id` and lists the accounts
+//! in the order the program expects them, see comments on `crate::Instruction`.
+
+use crate::{
+    find_pool_authority_address, find_pool_config_address, find_staking_account_address,
+    InsolvencyPolicy, Instruction as StakingInstruction, PostMaturityAccrual, StreamRate, Tier,
+};
+use solana_program::{
+    bpf_loader_upgradeable,
+    clock::UnixTimestamp,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+
+/// Staking account is created by the program at `find_staking_account_address(program_id, owner, position_index)`.
+#[allow(clippy::too_many_arguments)]
+pub fn stake(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    duration: u16,
+    amount: u64,
+    position_index: u64,
+) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    let (staking_account, _) = find_staking_account_address(program_id, owner, position_index);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(staking_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner_token_account, false),
+            AccountMeta::new(*pool_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(pool_config, false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: StakingInstruction::Stake { duration, amount, position_index }.pack(),
+    }
+}
+
+pub fn add_stake(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staking_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner_token_account, false),
+            AccountMeta::new(*pool_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(pool_config, false),
+        ],
+        data: StakingInstruction::AddStake { amount }.pack(),
+    }
+}
+
+pub fn withdraw_interest(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+) -> Instruction {
+    withdraw(
+        program_id,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        StakingInstruction::WithdrawInterest,
+    )
+}
+
+pub fn compound(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+) -> Instruction {
+    withdraw(
+        program_id,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        StakingInstruction::Compound,
+    )
+}
+
+pub fn withdraw_principal(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    withdraw(
+        program_id,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        StakingInstruction::WithdrawPrincipal { amount },
+    )
+}
+
+/// `rent_receiver` gets the lamports of the closed staking account.
+pub fn close_account(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    rent_receiver: &Pubkey,
+) -> Instruction {
+    let mut ix = withdraw(
+        program_id,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        StakingInstruction::CloseAccount,
+    );
+    ix.accounts.push(AccountMeta::new(*rent_receiver, false));
+    ix
+}
+
+/// Works only while the pool is paused with `PAUSE_INTEREST`, pays out the principal only.
+pub fn emergency_exit(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    rent_receiver: &Pubkey,
+) -> Instruction {
+    let mut ix = withdraw(
+        program_id,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        StakingInstruction::EmergencyExit,
+    );
+    ix.accounts.push(AccountMeta::new(*rent_receiver, false));
+    ix
+}
+
+/// `treasury` is `PoolConfig.treasury`, or any account when the pool has no treasury.
+pub fn early_unstake(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    rent_receiver: &Pubkey,
+    treasury: &Pubkey,
+) -> Instruction {
+    let mut ix = withdraw(
+        program_id,
+        staking_account,
+        owner,
+        owner_token_account,
+        pool_token_account,
+        StakingInstruction::EarlyUnstake,
+    );
+    ix.accounts.push(AccountMeta::new(*rent_receiver, false));
+    ix.accounts.push(AccountMeta::new(*treasury, false));
+    ix
+}
+
+pub fn set_auto_renew(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    auto_renew: bool,
+    compound: bool,
+) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staking_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(pool_config, false),
+        ],
+        data: StakingInstruction::SetAutoRenew { auto_renew, compound }.pack(),
+    }
+}
+
+/// With `new_holder_signs` the new holder must sign the transaction as well.
+pub fn transfer_position(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    new_holder: &Pubkey,
+    new_holder_signs: bool,
+) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    let mut accounts = vec![
+        AccountMeta::new(*staking_account, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(pool_config, false),
+    ];
+    if new_holder_signs {
+        accounts.push(AccountMeta::new_readonly(*new_holder, true));
+    }
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakingInstruction::TransferPosition { new_holder: *new_holder, new_holder_signs }.pack(),
+    }
+}
+
+/// `streams` are (stream vault, holder token account of the stream mint) for every registered stream
+/// in the order of `PoolConfig.reward_streams`.
+pub fn claim_rewards(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    streams: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let (pool_authority, _) = find_pool_authority_address(program_id);
+    let (pool_config, _) = find_pool_config_address(program_id);
+    let mut accounts = vec![
+        AccountMeta::new(*staking_account, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(pool_authority, false),
+        AccountMeta::new_readonly(pool_config, false),
+    ];
+    for (stream_vault, owner_token_account) in streams {
+        accounts.push(AccountMeta::new(*stream_vault, false));
+        accounts.push(AccountMeta::new(*owner_token_account, false));
+    }
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakingInstruction::ClaimRewards.pack(),
+    }
+}
+
+pub fn set_delegated_compounding(program_id: &Pubkey, staking_account: &Pubkey, owner: &Pubkey, enabled: bool) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staking_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(pool_config, false),
+        ],
+        data: StakingInstruction::SetDelegatedCompounding { enabled }.pack(),
+    }
+}
+
+/// Needs no signer, the tip is paid to `keeper_token_account`.
+pub fn crank_compound(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    keeper_token_account: &Pubkey,
+) -> Instruction {
+    let (pool_authority, _) = find_pool_authority_address(program_id);
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staking_account, false),
+            AccountMeta::new(*pool_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pool_authority, false),
+            AccountMeta::new(pool_config, false),
+            AccountMeta::new(*keeper_token_account, false),
+        ],
+        data: StakingInstruction::CrankCompound.pack(),
+    }
+}
+
+/// `duration` 0 renews into the current tier of the position.
+pub fn renew(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    duration: u16,
+    compound: bool,
+) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staking_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(pool_config, false),
+        ],
+        data: StakingInstruction::Renew { duration, compound }.pack(),
+    }
+}
+
+/// Needs no signer, works only for positions with auto renew enabled.
+pub fn crank_renew(program_id: &Pubkey, staking_account: &Pubkey) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staking_account, false),
+            AccountMeta::new(pool_config, false),
+        ],
+        data: StakingInstruction::CrankRenew.pack(),
+    }
+}
+
+/// `interest_vault` is `PoolConfig.reward_vault`, or the pool token account for pools without a reward vault.
+pub fn fund_rewards(
+    program_id: &Pubkey,
+    funder: &Pubkey,
+    funder_token_account: &Pubkey,
+    interest_vault: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(pool_config, false),
+            AccountMeta::new_readonly(*funder, true),
+            AccountMeta::new(*funder_token_account, false),
+            AccountMeta::new(*interest_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: StakingInstruction::FundRewards { amount }.pack(),
+    }
+}
+
+// WithdrawInterest, Compound, CloseAccount and WithdrawPrincipal share the account list.
+fn withdraw(
+    program_id: &Pubkey,
+    staking_account: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    pool_token_account: &Pubkey,
+    instruction: StakingInstruction,
+) -> Instruction {
+    let (pool_authority, _) = find_pool_authority_address(program_id);
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staking_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner_token_account, false),
+            AccountMeta::new(*pool_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pool_authority, false),
+            AccountMeta::new(pool_config, false),
+        ],
+        data: instruction.pack(),
+    }
+}
+
+/// `admin` must be the upgrade authority of the program.
+pub fn initialize_pool(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    stake_mint: &Pubkey,
+    pool_token_account: &Pubkey,
+    tiers: Vec<Tier>,
+) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    let (program_data, _) = Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(pool_config, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(*stake_mint, false),
+            AccountMeta::new_readonly(*pool_token_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(program_data, false),
+        ],
+        data: StakingInstruction::InitializePool { tiers }.pack(),
+    }
+}
+
+/// Pool paying interest in `reward_mint` from `reward_vault`, both owned by the pool authority.
+pub fn initialize_pool_with_reward_vault(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    stake_mint: &Pubkey,
+    pool_token_account: &Pubkey,
+    reward_mint: &Pubkey,
+    reward_vault: &Pubkey,
+    tiers: Vec<Tier>,
+) -> Instruction {
+    let mut ix = initialize_pool(program_id, admin, stake_mint, pool_token_account, tiers);
+    ix.accounts.push(AccountMeta::new_readonly(*reward_mint, false));
+    ix.accounts.push(AccountMeta::new_readonly(*reward_vault, false));
+    ix
+}
+
+/// Appends the accounts paying interest in pools with a reward vault to a withdraw, close or early unstake instruction.
+pub fn add_reward_accounts(ix: &mut Instruction, reward_vault: &Pubkey, owner_reward_token_account: &Pubkey) {
+    ix.accounts.push(AccountMeta::new(*reward_vault, false));
+    ix.accounts.push(AccountMeta::new(*owner_reward_token_account, false));
+}
+
+pub fn add_tier(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    duration: u16,
+    interest_numerator: u64,
+    interest_denominator: u64,
+    early_unstake_penalty_bps: u16,
+    early_unstake_forfeits_interest: bool,
+) -> Instruction {
+    let tier = Tier {
+        duration,
+        interest_numerator,
+        interest_denominator,
+        retired: false,
+        early_unstake_penalty_bps,
+        early_unstake_forfeits_interest,
+        ..Tier::default()
+    };
+    admin_instruction(program_id, admin, StakingInstruction::AddTier { tier })
+}
+
+pub fn update_tier_rate(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    duration: u16,
+    interest_numerator: u64,
+    interest_denominator: u64,
+) -> Instruction {
+    admin_instruction(
+        program_id,
+        admin,
+        StakingInstruction::UpdateTierRate { duration, interest_numerator, interest_denominator },
+    )
+}
+
+pub fn retire_tier(program_id: &Pubkey, admin: &Pubkey, duration: u16) -> Instruction {
+    admin_instruction(program_id, admin, StakingInstruction::RetireTier { duration })
+}
+
+pub fn set_tier_penalty(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    duration: u16,
+    early_unstake_penalty_bps: u16,
+    early_unstake_forfeits_interest: bool,
+) -> Instruction {
+    admin_instruction(
+        program_id,
+        admin,
+        StakingInstruction::SetTierPenalty { duration, early_unstake_penalty_bps, early_unstake_forfeits_interest },
+    )
+}
+
+pub fn set_tier_post_maturity(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    duration: u16,
+    post_maturity_accrual: PostMaturityAccrual,
+    flexible_interest_numerator: u64,
+    flexible_interest_denominator: u64,
+) -> Instruction {
+    admin_instruction(
+        program_id,
+        admin,
+        StakingInstruction::SetTierPostMaturity {
+            duration,
+            post_maturity_accrual,
+            flexible_interest_numerator,
+            flexible_interest_denominator,
+        },
+    )
+}
+
+pub fn set_keeper_tip(program_id: &Pubkey, admin: &Pubkey, keeper_tip_bps: u16) -> Instruction {
+    admin_instruction(program_id, admin, StakingInstruction::SetKeeperTip { keeper_tip_bps })
+}
+
+pub fn add_reward_stream(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    mint: &Pubkey,
+    stream_vault: &Pubkey,
+    start: UnixTimestamp,
+    end: UnixTimestamp,
+    rates: Vec<StreamRate>,
+) -> Instruction {
+    let mut ix = admin_instruction(program_id, admin, StakingInstruction::AddRewardStream { start, end, rates });
+    ix.accounts.push(AccountMeta::new_readonly(*mint, false));
+    ix.accounts.push(AccountMeta::new_readonly(*stream_vault, false));
+    ix
+}
+
+pub fn remove_reward_stream(program_id: &Pubkey, admin: &Pubkey, id: u64) -> Instruction {
+    admin_instruction(program_id, admin, StakingInstruction::RemoveRewardStream { id })
+}
+
+pub fn set_insolvency_policy(program_id: &Pubkey, admin: &Pubkey, insolvency_policy: InsolvencyPolicy) -> Instruction {
+    admin_instruction(program_id, admin, StakingInstruction::SetInsolvencyPolicy { insolvency_policy })
+}
+
+/// `paused` is a combination of `PAUSE_STAKING` and `PAUSE_INTEREST`, 0 resumes the pool.
+pub fn set_pause(program_id: &Pubkey, admin: &Pubkey, paused: u8) -> Instruction {
+    admin_instruction(program_id, admin, StakingInstruction::SetPause { paused })
+}
+
+pub fn set_treasury(program_id: &Pubkey, admin: &Pubkey, treasury: &Pubkey) -> Instruction {
+    let mut ix = admin_instruction(program_id, admin, StakingInstruction::SetTreasury);
+    ix.accounts.push(AccountMeta::new_readonly(*treasury, false));
+    ix
+}
+
+// Admin instructions only touch the pool config.
+fn admin_instruction(program_id: &Pubkey, admin: &Pubkey, instruction: StakingInstruction) -> Instruction {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(pool_config, false),
+            AccountMeta::new_readonly(*admin, true),
+        ],
+        data: instruction.pack(),
+    }
+}
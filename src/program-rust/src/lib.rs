@@ -1,55 +1,281 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable,
     clock::{UnixTimestamp, Clock},
-    entrypoint,
     entrypoint::ProgramResult,
     msg,
-    program_error::ProgramError,
+    program_error::{PrintProgramError, ProgramError},
     pubkey::Pubkey,
-    system_program::{check_id, ID as SYSTEM_PROGRAM_ID},
+    rent::Rent,
+    system_instruction,
+    system_program::ID as SYSTEM_PROGRAM_ID,
     program::{invoke, invoke_signed},
+    program_pack::Pack,
 };
 use spl_token::{
-    check_program_account,
+    state::{Account as TokenAccount, Mint},
     ID as SPL_TOKEN_PROGRAM_ID,
-    instruction::transfer,
 };
 use std::convert::TryFrom;
 
-const ALLOWED_DURATIONS_DAYS: [u16; 2] = [180, 360];
-const ANNUAL_INTEREST_NOMITATORS: [u64; 2] = [15, 17];
-const ANNUAL_INTEREST_DENOMITATORS: [u64; 2] = [100, 100];
+pub mod error;
+#[cfg(feature = "client")]
+pub mod instruction;
+#[cfg(feature = "client")]
+pub mod cpi;
+use error::StakingError;
+
 const SECONDS_PER_YEAR: u64 = 360 * 24 * 3600;
 const INTEREST_ALLOCATION_PERIOD_SECONDS: u64 = 60;
 const ALLOCATION_PERDIODS_PER_YEAR: u64 = SECONDS_PER_YEAR / INTEREST_ALLOCATION_PERIOD_SECONDS;
-const INTEREST_NOMITATORS_PER_ALLOCATION_PERIOD: [u64; 2] = ANNUAL_INTEREST_NOMITATORS;
-const INTEREST_DENOMITATORS_PER_ALLOCATION_PERIOD: [u64; 2] = [
-    ANNUAL_INTEREST_DENOMITATORS[0] * ALLOCATION_PERDIODS_PER_YEAR,
-    ANNUAL_INTEREST_DENOMITATORS[1] * ALLOCATION_PERDIODS_PER_YEAR,
-];
 const POOL_ADDRESS_SEED: &[u8] = &[0x50, 0x00, 0x00, 0x10, 0x20, 0xad, 0x35];
+const BPS_DENOMINATOR: u16 = 10_000;
+const MAX_KEEPER_TIP_BPS: u16 = 1_000;
+const REWARD_CLAIM_WINDOW_SECONDS: i64 = 30 * 24 * 3600;
+/// PoolConfig.paused flags: new locks with Stake, AddStake, Renew and CrankRenew
+pub const PAUSE_STAKING: u8 = 1;
+/// PoolConfig.paused flags: instructions paying, compounding or settling interest and stream rewards
+pub const PAUSE_INTEREST: u8 = 2;
+/// PoolConfig.paused flags: holder settings of a position, SetAutoRenew, TransferPosition and SetDelegatedCompounding
+pub const PAUSE_POSITIONS: u8 = 4;
+/// Everything except EmergencyExit, FundRewards and admin instructions
+pub const PAUSE_ALL: u8 = PAUSE_STAKING | PAUSE_INTEREST | PAUSE_POSITIONS;
+const POOL_CONFIG_SEED: &[u8] = &[0x50, 0x00, 0x00, 0x10, 0xc0, 0x4f, 0x16];
+const STAKING_ACCOUNT_SEED: &[u8] = &[0x50, 0x00, 0x00, 0x10, 0x57, 0xa4, 0x3e];
+
+/// Address of the pool config account and its canonical bump
+pub fn find_pool_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POOL_CONFIG_SEED], program_id)
+}
+
+/// Address of the pool manager (owner of the pool token account) and its canonical bump
+pub fn find_pool_authority_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POOL_ADDRESS_SEED], program_id)
+}
+
+/// Address of the holder's staking account number `position_index` and its canonical bump.
+/// Clients find all positions of a holder by walking indexes from 0.
+pub fn find_staking_account_address(program_id: &Pubkey, holder: &Pubkey, position_index: u64) -> (Pubkey, u8) {
+    let (pool_config, _) = find_pool_config_address(program_id);
+    Pubkey::find_program_address(
+        &[STAKING_ACCOUNT_SEED, pool_config.as_ref(), holder.as_ref(), &position_index.to_le_bytes()],
+        program_id,
+    )
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Instruction {
-    // Checks and initializes an empty account.
+    // Creates the staking account and moves the tokens into the pool.
+    // Rejected when PoolConfig.reward_reserve does not cover the interest of the full lock on top of
+    // the interest already promised to other positions.
     // Accepted accounts:
+    //    [writable] - staking account, PDA of (STAKING_ACCOUNT_SEED, pool config, owner, position_index)
     //    [readable, signed] - owner account, signed, mostly to avoid fat finger errors.
-    //    [writable] - bets account
+    //    [writable] - owner token account
+    //    [writable] - pool token account
+    //    [readable] - token program
+    //    [writable] - pool config account
+    //    [writable, signed] - payer account, pays rent for the staking account
+    //    [readable] - system program
     Stake{
-        duration: u16,  // allowed 180, 360, (ALLOWED_DURATIONS_DAYS)
+        duration: u16,  // one of PoolConfig.tiers
+        amount: u64,
+        position_index: u64,
+    },
+    // WithdrawInterest, Compound, CloseAccount and WithdrawPrincipal accept the same accounts:
+    //    [writable] - staking account
+    //    [readable, signed] - owner account
+    //    [writable] - owner token account
+    //    [writable] - pool token account
+    //    [readable] - token program
+    //    [readable] - pool manager account, PDA of POOL_ADDRESS_SEED with PoolConfig.authority_bump
+    //    [writable] - pool config account
+    //    [writable] - rent receiver, CloseAccount only, gets the lamports of the staking account
+    //    [writable] - reward vault, only for pools with PoolConfig.reward_vault
+    //    [writable] - holder token account of the reward mint, only for pools with PoolConfig.reward_vault
+    // Compound is not available in pools with a reward vault.
+    // Interest the pool can not pay now is owed in extra_not_withdrawn_tokens according to PoolConfig.insolvency_policy,
+    // such a position is not wiped by CloseAccount and EarlyUnstake until the debt is paid with WithdrawInterest.
+    // The same holds for unclaimed stream rewards until ClaimRewards.
+    WithdrawInterest,
+    Compound,
+    // Pays out principal with interest and wipes the staking account.
+    // A position with unclaimed stream rewards stays open without principal until ClaimRewards and another CloseAccount.
+    CloseAccount,
+    // Creates the pool config PDA and fills it with the pool economics.
+    // Only the upgrade authority of the program can initialize the pool and becomes its admin.
+    // Accepted accounts:
+    //    [writable] - pool config account, PDA of POOL_CONFIG_SEED
+    //    [writable, signed] - admin account, pays for the pool config account
+    //    [readable] - stake token mint
+    //    [readable] - pool token account (vault), owned by the POOL_ADDRESS_SEED PDA
+    //    [readable] - system program
+    //    [readable] - program data account of this program, its upgrade authority must be the admin
+    //    [readable] - reward token mint, optional, interest is paid in the stake mint from the vault without it
+    //    [readable] - reward vault, owned by the POOL_ADDRESS_SEED PDA, required with the reward mint
+    InitializePool{
+        tiers: Vec<Tier>,
+    },
+    // AddTier, UpdateTierRate and RetireTier are admin-only and accept the same accounts:
+    //    [writable] - pool config account
+    //    [readable, signed] - admin account
+    AddTier{
+        tier: Tier,
+    },
+    // New rate is applied only to stakes made after the update.
+    UpdateTierRate{
+        duration: u16,
+        interest_numerator: u64,
+        interest_denominator: u64,
+    },
+    // Closes the tier for new stakes, existing positions keep accruing.
+    RetireTier{
+        duration: u16,
+    },
+    // Exit before the lock is over, the holder pays the penalty locked in at stake time.
+    // Accepted accounts are the same as for CloseAccount plus:
+    //    [writable] - treasury token account, PoolConfig.treasury; when treasury is not set
+    //                 the penalty stays in the pool token account and any account can be passed,
+    //                 pools with a reward vault refuse penalties without a treasury
+    // The reward vault accounts, if the pool has one, go after the treasury.
+    EarlyUnstake,
+    // New penalty is applied only to stakes made after the update. Accounts as for AddTier.
+    // Pools with a reward vault need a treasury before any penalty is set.
+    SetTierPenalty{
+        duration: u16,
+        early_unstake_penalty_bps: u16,
+        early_unstake_forfeits_interest: bool,
+    },
+    // Sets the token account receiving early unstake penalties. Accounts as for AddTier plus:
+    //    [readable] - treasury token account of the stake mint
+    SetTreasury,
+    // Tops up an open position, the lock starts over on the tier terms the position was opened with.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [readable, signed] - owner account
+    //    [writable] - owner token account
+    //    [writable] - pool token account
+    //    [readable] - token program
+    //    [writable] - pool config account
+    AddStake{
+        amount: u64,
+    },
+    // Pays out the accrued interest and a part of the principal after the lock is over,
+    // the rest keeps earning. Accounts as for WithdrawInterest.
+    WithdrawPrincipal{
         amount: u64,
-        bump_seed: u8,
     },
-    WithdrawInterest{
-        bump_seed: u8,
+    // Lets anyone renew the position with CrankRenew when its lock is over.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [readable, signed] - owner account
+    //    [readable] - pool config account
+    SetAutoRenew{
+        auto_renew: bool,
+        compound: bool, // add the accrued interest to the principal on renew
+    },
+    // Starts a new lock from now after the current one is over, the time after maturity accrues by the old tier.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [readable, signed] - owner account
+    //    [writable] - pool config account
+    Renew{
+        duration: u16,  // one of PoolConfig.tiers, 0 keeps the current tier
+        compound: bool, // otherwise the interest is carried in extra_not_withdrawn_tokens
+    },
+    // Permissionless Renew of a position with auto_renew into the same tier.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [writable] - pool config account
+    CrankRenew,
+    // Changes how matured positions of the tier accrue, applied only to stakes and renewals made after the update.
+    // Accounts as for AddTier.
+    SetTierPostMaturity{
+        duration: u16,
+        post_maturity_accrual: PostMaturityAccrual,
+        flexible_interest_numerator: u64,   // used only by PostMaturityAccrual::Flexible
+        flexible_interest_denominator: u64,
+    },
+    // Moves the position to another wallet, accrued interest goes along in extra_not_withdrawn_tokens.
+    // To settle it to the current holder put WithdrawInterest before this instruction in the same transaction.
+    // The position stays at the PDA derived from the first holder.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [readable, signed] - owner account
+    //    [writable] - pool config account
+    //    [readable, signed] - new holder account, only when new_holder_signs
+    TransferPosition{
+        new_holder: Pubkey,
+        new_holder_signs: bool, // protects from a typo in new_holder
+    },
+    // Lets anyone compound the position with CrankCompound.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [readable, signed] - owner account
+    //    [readable] - pool config account
+    SetDelegatedCompounding{
+        enabled: bool,
+    },
+    // Share of the compounded interest paid to the keeper, at most MAX_KEEPER_TIP_BPS. Accounts as for AddTier.
+    SetKeeperTip{
+        keeper_tip_bps: u16,
+    },
+    // Permissionless Compound of a position with delegated compounding, the keeper gets the tip.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [writable] - pool token account
+    //    [readable] - token program
+    //    [readable] - pool manager account
+    //    [writable] - pool config account
+    //    [writable] - keeper token account of the stake mint
+    CrankCompound,
+    // Pays everything accrued by the reward streams of the pool.
+    // Accepted accounts:
+    //    [writable] - staking account
+    //    [readable, signed] - owner account
+    //    [readable] - token program
+    //    [readable] - pool manager account
+    //    [readable] - pool config account
+    //    then for every registered stream in the order of PoolConfig.reward_streams:
+    //    [writable] - stream vault
+    //    [writable] - holder token account of the stream mint
+    ClaimRewards,
+    // Registers a reward campaign in a free slot. Accounts as for AddTier plus:
+    //    [readable] - stream token mint
+    //    [readable] - stream vault, owned by the POOL_ADDRESS_SEED PDA
+    AddRewardStream{
+        start: UnixTimestamp,
+        end: UnixTimestamp,
+        rates: Vec<StreamRate>, // positions of tiers without a rate get nothing
+    },
+    // Frees the slot of a stream REWARD_CLAIM_WINDOW_SECONDS after its end, unclaimed rewards are forfeited.
+    // Accounts as for AddTier.
+    RemoveRewardStream{
+        id: u64,
+    },
+    // Deposits tokens for interest payouts, anyone can fund the pool.
+    // Accepted accounts:
+    //    [writable] - pool config account
+    //    [readable, signed] - funder account
+    //    [writable] - funder token account of the interest mint
+    //    [writable] - PoolConfig.reward_vault, or the pool token account for pools without a reward vault
+    //    [readable] - token program
+    FundRewards{
+        amount: u64,
     },
-    Compound{
-        bump_seed: u8,
+    // Chooses what happens to interest the reward reserve can not cover. Accounts as for AddTier.
+    SetInsolvencyPolicy{
+        insolvency_policy: InsolvencyPolicy,
     },
-    CloseAccount{
-        bump_seed: u8,
+    // Replaces PoolConfig.paused, every change is logged. Accounts as for AddTier.
+    SetPause{
+        paused: u8, // PAUSE_STAKING | PAUSE_INTEREST | PAUSE_POSITIONS
     },
+    // Pays out the whole principal without penalty while PAUSE_INTEREST is set, the lock is ignored.
+    // Interest accrued so far is owed in extra_not_withdrawn_tokens, such a position stays open until it is paid.
+    // Accounts as for CloseAccount, the reward vault accounts are not needed.
+    EmergencyExit,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -57,49 +283,399 @@ pub enum WithdrawStrategy {
     InterestOnly,
     Compound,
     CloseAccount,
+    Principal(u64),
+}
+
+/// Tokens to send to the holder: principal from the vault, interest from the reward vault if the pool has one
+#[derive(Debug, PartialEq)]
+pub struct Payout {
+    pub principal: u64,
+    pub interest: u64,
+}
+
+impl Payout {
+    pub fn total(&self) -> Result<u64, ProgramError> {
+        self.principal.checked_add(self.interest).ok_or_else(|| StakingError::MathOverflow.into())
+    }
 }
 
+// Every instruction starts with two bytes: tag and version of its layout.
+// New fields are added by bumping the version. An old version keeps being accepted while the program
+// can give it the same meaning with defaults for the new fields. When the accounts of an instruction
+// change, its old versions are dropped and rejected as unknown, clients have to move to the latest one.
+// Version 1 of WithdrawInterest and Compound carried a bump seed of the pool manager,
+// it is ignored now, the canonical bump is stored in PoolConfig.
+// Stake versions 1 and 2 expected a staking account created by the client and are dropped.
+// CloseAccount versions 1 and 2 had no rent receiver and are dropped.
+// Version 1 of InitializePool and AddTier has no early unstake penalty in tiers, such tiers
+// allow to exit early for the accrued interest only.
+// Versions 1 and 2 of InitializePool and AddTier have no post maturity policy, such tiers
+// keep accruing at the full rate after maturity.
 impl Instruction {
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        use std::convert::TryInto;
         use ProgramError::InvalidInstructionData;
         let (&tag, rest) = input.split_first().ok_or(InvalidInstructionData)?;
-        Ok(match tag {
-            0 => {
-                let (duration, rest) = rest.split_at(2);
-                let duration = duration
-                    .try_into()
-                    .ok()
-                    .map(u16::from_le_bytes)
-                    .ok_or(InvalidInstructionData)?;
-                let (amount, bump_seed) = rest.split_at(8);
-                let amount = amount
-                    .try_into()
-                    .ok()
-                    .map(u64::from_le_bytes)
-                    .ok_or(InvalidInstructionData)?;
-                let (bump_seed, _nothing) = bump_seed.split_first().ok_or(InvalidInstructionData)?;
-                msg!("Duration {} Amount {} Bump {}", duration, amount, bump_seed);
+        let (&version, rest) = rest.split_first().ok_or(InvalidInstructionData)?;
+        let (instruction, rest) = match (tag, version) {
+            (0, 3) => {
+                let (duration, rest) = unpack_u16(rest)?;
+                let (amount, rest) = unpack_u64(rest)?;
+                let (position_index, rest) = unpack_u64(rest)?;
+                (Self::Stake { duration, amount, position_index }, rest)
+            },
+            (1, 1) => (Self::WithdrawInterest, unpack_u8(rest)?.1),
+            (1, 2) => (Self::WithdrawInterest, rest),
+            (2, 1) => (Self::Compound, unpack_u8(rest)?.1),
+            (2, 2) => (Self::Compound, rest),
+            (3, 3) => (Self::CloseAccount, rest),
+            (4, 1) | (4, 2) | (4, 3) => {
+                let (tiers_count, mut rest) = unpack_u8(rest)?;
+                let mut tiers = Vec::with_capacity(usize::from(tiers_count).min(MAX_TIERS));
+                for _ in 0..tiers_count {
+                    let (tier, tail) = unpack_tier(rest)?;
+                    let (tier, tail) = if version == 1 { (tier, tail) } else { unpack_tier_penalty(tier, tail)? };
+                    let (tier, tail) = if version < 3 { (tier, tail) } else { unpack_tier_post_maturity(tier, tail)? };
+                    tiers.push(tier);
+                    rest = tail;
+                }
+                (Self::InitializePool { tiers }, rest)
+            },
+            (5, 1) => {
+                let (tier, rest) = unpack_tier(rest)?;
+                (Self::AddTier { tier }, rest)
+            },
+            (5, 2) => {
+                let (tier, rest) = unpack_tier(rest)?;
+                let (tier, rest) = unpack_tier_penalty(tier, rest)?;
+                (Self::AddTier { tier }, rest)
+            },
+            (5, 3) => {
+                let (tier, rest) = unpack_tier(rest)?;
+                let (tier, rest) = unpack_tier_penalty(tier, rest)?;
+                let (tier, rest) = unpack_tier_post_maturity(tier, rest)?;
+                (Self::AddTier { tier }, rest)
+            },
+            (6, 1) => {
+                let (Tier { duration, interest_numerator, interest_denominator, .. }, rest) = unpack_tier(rest)?;
+                (Self::UpdateTierRate { duration, interest_numerator, interest_denominator }, rest)
+            },
+            (7, 1) => {
+                let (duration, rest) = unpack_u16(rest)?;
+                (Self::RetireTier { duration }, rest)
+            },
+            (8, 1) => (Self::EarlyUnstake, rest),
+            (9, 1) => {
+                let (duration, rest) = unpack_u16(rest)?;
+                let (early_unstake_penalty_bps, rest) = unpack_u16(rest)?;
+                let (early_unstake_forfeits_interest, rest) = unpack_bool(rest)?;
+                (Self::SetTierPenalty { duration, early_unstake_penalty_bps, early_unstake_forfeits_interest }, rest)
+            },
+            (10, 1) => (Self::SetTreasury, rest),
+            (11, 1) => {
+                let (amount, rest) = unpack_u64(rest)?;
+                (Self::AddStake { amount }, rest)
+            },
+            (12, 1) => {
+                let (amount, rest) = unpack_u64(rest)?;
+                (Self::WithdrawPrincipal { amount }, rest)
+            },
+            (13, 1) => {
+                let (auto_renew, rest) = unpack_bool(rest)?;
+                let (compound, rest) = unpack_bool(rest)?;
+                (Self::SetAutoRenew { auto_renew, compound }, rest)
+            },
+            (14, 1) => {
+                let (duration, rest) = unpack_u16(rest)?;
+                let (compound, rest) = unpack_bool(rest)?;
+                (Self::Renew { duration, compound }, rest)
+            },
+            (15, 1) => (Self::CrankRenew, rest),
+            (16, 1) => {
+                let (duration, rest) = unpack_u16(rest)?;
+                let (Tier { post_maturity_accrual, flexible_interest_numerator, flexible_interest_denominator, .. }, rest) =
+                    unpack_tier_post_maturity(Tier { duration, ..Tier::default() }, rest)?;
+                (Self::SetTierPostMaturity { duration, post_maturity_accrual, flexible_interest_numerator, flexible_interest_denominator }, rest)
+            },
+            (17, 1) => {
+                let (new_holder, rest) = unpack_pubkey(rest)?;
+                let (new_holder_signs, rest) = unpack_bool(rest)?;
+                (Self::TransferPosition { new_holder, new_holder_signs }, rest)
+            },
+            (18, 1) => {
+                let (enabled, rest) = unpack_bool(rest)?;
+                (Self::SetDelegatedCompounding { enabled }, rest)
+            },
+            (19, 1) => {
+                let (keeper_tip_bps, rest) = unpack_u16(rest)?;
+                (Self::SetKeeperTip { keeper_tip_bps }, rest)
+            },
+            (20, 1) => (Self::CrankCompound, rest),
+            (21, 1) => (Self::ClaimRewards, rest),
+            (22, 1) => {
+                let (start, rest) = unpack_i64(rest)?;
+                let (end, rest) = unpack_i64(rest)?;
+                let (rates_count, mut rest) = unpack_u8(rest)?;
+                let mut rates = Vec::with_capacity(usize::from(rates_count).min(MAX_TIERS));
+                for _ in 0..rates_count {
+                    let (Tier { duration, interest_numerator, interest_denominator, .. }, tail) = unpack_tier(rest)?;
+                    rates.push(StreamRate { duration, numerator: interest_numerator, denominator: interest_denominator });
+                    rest = tail;
+                }
+                (Self::AddRewardStream { start, end, rates }, rest)
+            },
+            (23, 1) => {
+                let (id, rest) = unpack_u64(rest)?;
+                (Self::RemoveRewardStream { id }, rest)
+            },
+            (24, 1) => {
+                let (amount, rest) = unpack_u64(rest)?;
+                (Self::FundRewards { amount }, rest)
+            },
+            (25, 1) => {
+                let (insolvency_policy, rest) = match unpack_u8(rest)? {
+                    (0, rest) => (InsolvencyPolicy::Revert, rest),
+                    (1, rest) => (InsolvencyPolicy::FirstCome, rest),
+                    (2, rest) => (InsolvencyPolicy::ProRata, rest),
+                    _ => return Err(InvalidInstructionData),
+                };
+                (Self::SetInsolvencyPolicy { insolvency_policy }, rest)
+            },
+            (26, 1) => {
+                let (paused, rest) = unpack_u8(rest)?;
+                (Self::SetPause { paused }, rest)
+            },
+            (27, 1) => (Self::EmergencyExit, rest),
+            _ => {
+                msg!("Unknown instruction {} version {}", tag, version);
+                return Err(InvalidInstructionData);
+            }
+        };
+        if !rest.is_empty() {
+            msg!("Instruction {} version {} has {} extra bytes", tag, version, rest.len());
+            return Err(InvalidInstructionData);
+        }
+        Ok(instruction)
+    }
 
-                Self::Stake { duration: duration as u16, amount, bump_seed: *bump_seed }
+    /// Serializes the instruction with the latest layout version, `unpack(&pack())` always gives it back.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Self::Stake { duration, amount, position_index } => {
+                buf.extend_from_slice(&[0, 3]);
+                buf.extend_from_slice(&duration.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&position_index.to_le_bytes());
+            },
+            Self::WithdrawInterest => {
+                buf.extend_from_slice(&[1, 2]);
+            },
+            Self::Compound => {
+                buf.extend_from_slice(&[2, 2]);
+            },
+            Self::CloseAccount => {
+                buf.extend_from_slice(&[3, 3]);
+            },
+            Self::InitializePool { tiers } => {
+                buf.extend_from_slice(&[4, 3, tiers.len() as u8]);
+                for tier in tiers {
+                    pack_tier(tier.duration, tier.interest_numerator, tier.interest_denominator, &mut buf);
+                    pack_tier_penalty(tier, &mut buf);
+                    pack_tier_post_maturity(tier.post_maturity_accrual, tier.flexible_interest_numerator, tier.flexible_interest_denominator, &mut buf);
+                }
+            },
+            Self::AddTier { tier } => {
+                buf.extend_from_slice(&[5, 3]);
+                pack_tier(tier.duration, tier.interest_numerator, tier.interest_denominator, &mut buf);
+                pack_tier_penalty(tier, &mut buf);
+                pack_tier_post_maturity(tier.post_maturity_accrual, tier.flexible_interest_numerator, tier.flexible_interest_denominator, &mut buf);
+            },
+            Self::UpdateTierRate { duration, interest_numerator, interest_denominator } => {
+                buf.extend_from_slice(&[6, 1]);
+                pack_tier(*duration, *interest_numerator, *interest_denominator, &mut buf);
+            },
+            Self::RetireTier { duration } => {
+                buf.extend_from_slice(&[7, 1]);
+                buf.extend_from_slice(&duration.to_le_bytes());
+            },
+            Self::EarlyUnstake => {
+                buf.extend_from_slice(&[8, 1]);
+            },
+            Self::SetTierPenalty { duration, early_unstake_penalty_bps, early_unstake_forfeits_interest } => {
+                buf.extend_from_slice(&[9, 1]);
+                buf.extend_from_slice(&duration.to_le_bytes());
+                buf.extend_from_slice(&early_unstake_penalty_bps.to_le_bytes());
+                buf.push(*early_unstake_forfeits_interest as u8);
+            },
+            Self::SetTreasury => {
+                buf.extend_from_slice(&[10, 1]);
             },
-            1 => {
-                let (bump_seed, _nothing) = rest.split_first().ok_or(InvalidInstructionData)?;
-                Self::WithdrawInterest { bump_seed: *bump_seed }
+            Self::AddStake { amount } => {
+                buf.extend_from_slice(&[11, 1]);
+                buf.extend_from_slice(&amount.to_le_bytes());
             },
-            2 => {
-                let (bump_seed, _nothing) = rest.split_first().ok_or(InvalidInstructionData)?;
-                Self::Compound { bump_seed: *bump_seed }
+            Self::WithdrawPrincipal { amount } => {
+                buf.extend_from_slice(&[12, 1]);
+                buf.extend_from_slice(&amount.to_le_bytes());
             },
-            3 => {
-                let (bump_seed, _nothing) = rest.split_first().ok_or(InvalidInstructionData)?;
-                Self::CloseAccount { bump_seed: *bump_seed }
+            Self::SetAutoRenew { auto_renew, compound } => {
+                buf.extend_from_slice(&[13, 1, *auto_renew as u8, *compound as u8]);
             },
-            _ => unreachable!()
-        })
+            Self::Renew { duration, compound } => {
+                buf.extend_from_slice(&[14, 1]);
+                buf.extend_from_slice(&duration.to_le_bytes());
+                buf.push(*compound as u8);
+            },
+            Self::CrankRenew => {
+                buf.extend_from_slice(&[15, 1]);
+            },
+            Self::SetTierPostMaturity { duration, post_maturity_accrual, flexible_interest_numerator, flexible_interest_denominator } => {
+                buf.extend_from_slice(&[16, 1]);
+                buf.extend_from_slice(&duration.to_le_bytes());
+                pack_tier_post_maturity(*post_maturity_accrual, *flexible_interest_numerator, *flexible_interest_denominator, &mut buf);
+            },
+            Self::TransferPosition { new_holder, new_holder_signs } => {
+                buf.extend_from_slice(&[17, 1]);
+                buf.extend_from_slice(new_holder.as_ref());
+                buf.push(*new_holder_signs as u8);
+            },
+            Self::SetDelegatedCompounding { enabled } => {
+                buf.extend_from_slice(&[18, 1, *enabled as u8]);
+            },
+            Self::SetKeeperTip { keeper_tip_bps } => {
+                buf.extend_from_slice(&[19, 1]);
+                buf.extend_from_slice(&keeper_tip_bps.to_le_bytes());
+            },
+            Self::CrankCompound => {
+                buf.extend_from_slice(&[20, 1]);
+            },
+            Self::ClaimRewards => {
+                buf.extend_from_slice(&[21, 1]);
+            },
+            Self::AddRewardStream { start, end, rates } => {
+                buf.extend_from_slice(&[22, 1]);
+                buf.extend_from_slice(&start.to_le_bytes());
+                buf.extend_from_slice(&end.to_le_bytes());
+                buf.push(rates.len() as u8);
+                for rate in rates {
+                    pack_tier(rate.duration, rate.numerator, rate.denominator, &mut buf);
+                }
+            },
+            Self::RemoveRewardStream { id } => {
+                buf.extend_from_slice(&[23, 1]);
+                buf.extend_from_slice(&id.to_le_bytes());
+            },
+            Self::FundRewards { amount } => {
+                buf.extend_from_slice(&[24, 1]);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            },
+            Self::SetInsolvencyPolicy { insolvency_policy } => {
+                buf.extend_from_slice(&[25, 1, *insolvency_policy as u8]);
+            },
+            Self::SetPause { paused } => {
+                buf.extend_from_slice(&[26, 1, *paused]);
+            },
+            Self::EmergencyExit => {
+                buf.extend_from_slice(&[27, 1]);
+            },
+        }
+        buf
+    }
+}
+
+fn unpack_u8(input: &[u8]) -> Result<(u8, &[u8]), ProgramError> {
+    let (&value, rest) = input.split_first().ok_or(ProgramError::InvalidInstructionData)?;
+    Ok((value, rest))
+}
+
+fn unpack_u16(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+    use std::convert::TryInto;
+    let value = input
+        .get(..2)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u16::from_le_bytes)
+        .ok_or(ProgramError::InvalidInstructionData)?;
+    Ok((value, &input[2..]))
+}
+
+fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+    use std::convert::TryInto;
+    let value = input
+        .get(..8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or(ProgramError::InvalidInstructionData)?;
+    Ok((value, &input[8..]))
+}
+
+fn unpack_i64(input: &[u8]) -> Result<(i64, &[u8]), ProgramError> {
+    let (value, rest) = unpack_u64(input)?;
+    Ok((value as i64, rest))
+}
+
+fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+    if input.len() < 32 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let (key, rest) = input.split_at(32);
+    Ok((Pubkey::new(key), rest))
+}
+
+fn unpack_bool(input: &[u8]) -> Result<(bool, &[u8]), ProgramError> {
+    match unpack_u8(input)? {
+        (0, rest) => Ok((false, rest)),
+        (1, rest) => Ok((true, rest)),
+        _ => Err(ProgramError::InvalidInstructionData),
     }
 }
 
+// duration (u16), interest_numerator (u64), interest_denominator (u64)
+fn unpack_tier(input: &[u8]) -> Result<(Tier, &[u8]), ProgramError> {
+    let (duration, rest) = unpack_u16(input)?;
+    let (interest_numerator, rest) = unpack_u64(rest)?;
+    let (interest_denominator, rest) = unpack_u64(rest)?;
+    Ok((Tier {
+        duration,
+        interest_numerator,
+        interest_denominator,
+        ..Tier::default()
+    }, rest))
+}
+
+// early_unstake_penalty_bps (u16), early_unstake_forfeits_interest (u8)
+fn unpack_tier_penalty(tier: Tier, input: &[u8]) -> Result<(Tier, &[u8]), ProgramError> {
+    let (early_unstake_penalty_bps, rest) = unpack_u16(input)?;
+    let (early_unstake_forfeits_interest, rest) = unpack_bool(rest)?;
+    Ok((Tier { early_unstake_penalty_bps, early_unstake_forfeits_interest, ..tier }, rest))
+}
+
+fn unpack_tier_post_maturity(tier: Tier, input: &[u8]) -> Result<(Tier, &[u8]), ProgramError> {
+    let (post_maturity_accrual, rest) = match unpack_u8(input)? {
+        (0, rest) => (PostMaturityAccrual::FullRate, rest),
+        (1, rest) => (PostMaturityAccrual::Stop, rest),
+        (2, rest) => (PostMaturityAccrual::Flexible, rest),
+        _ => return Err(ProgramError::InvalidInstructionData),
+    };
+    let (flexible_interest_numerator, rest) = unpack_u64(rest)?;
+    let (flexible_interest_denominator, rest) = unpack_u64(rest)?;
+    Ok((Tier { post_maturity_accrual, flexible_interest_numerator, flexible_interest_denominator, ..tier }, rest))
+}
+fn pack_tier(duration: u16, interest_numerator: u64, interest_denominator: u64, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&duration.to_le_bytes());
+    buf.extend_from_slice(&interest_numerator.to_le_bytes());
+    buf.extend_from_slice(&interest_denominator.to_le_bytes());
+}
+
+fn pack_tier_penalty(tier: &Tier, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&tier.early_unstake_penalty_bps.to_le_bytes());
+    buf.push(tier.early_unstake_forfeits_interest as u8);
+}
+fn pack_tier_post_maturity(post_maturity_accrual: PostMaturityAccrual, numerator: u64, denominator: u64, buf: &mut Vec<u8>) {
+    buf.push(post_maturity_accrual as u8);
+    buf.extend_from_slice(&numerator.to_le_bytes());
+    buf.extend_from_slice(&denominator.to_le_bytes());
+}
+
 
 /// Define the type of state stored in accounts
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
@@ -119,246 +695,4949 @@ pub struct StakingAccount {
     pub extra_not_withdrawn_tokens: u64,   // это самое сложное. Если в результате ре-стейкинга у нас изменяется
                                            // поле token_amount, то нам надо сохранить информацию о процентах,
                                            // набежавших до ре-стейкинга.
+    pub interest_numerator: u64,   // ставка тира на момент стейка, последующие изменения тира
+    pub interest_denominator: u64, // на эту позицию не влияют
+    pub closed: bool,              // позиция закрыта, все остальные поля обнулены
+    pub early_unstake_penalty_bps: u16,        // штраф тира за досрочный выход на момент стейка
+    pub early_unstake_forfeits_interest: bool, // при досрочном выходе набежавшие проценты сгорают
+    pub lock_start: UnixTimestamp, // начало текущего лока, сдвигается пополнением и продлением
+    pub auto_renew: bool,          // по окончании лока позицию может продлить кто угодно
+    pub auto_renew_compound: bool, // при автопродлении проценты добавляются к телу
+    pub post_maturity_accrual: PostMaturityAccrual, // начисление после окончания лока, зафиксировано на момент стейка
+    pub flexible_interest_numerator: u64,
+    pub flexible_interest_denominator: u64,
+    pub delegated_compounding: bool, // проценты может капитализировать кто угодно за чаевые из пула
+    pub reward_streams: [StreamPosition; MAX_REWARD_STREAMS], // по слотам PoolConfig.reward_streams
+    pub reserved_interest: u64,    // доля позиции в PoolConfig.interest_liability на момент последнего расчета
 }
-const STAKING_ACCOUNT_SIZE: usize = 1 + 32 + 8 + 2 + 8 + 8 + 8;
+pub const STAKING_ACCOUNT_SIZE: usize = 1 + 32 + 8 + 2 + 8 + 8 + 8 + 8 + 8 + 1 + 2 + 1 + 8 + 1 + 1 + 1 + 8 + 8 + 1
+    + MAX_REWARD_STREAMS * STREAM_POSITION_SIZE + 8;
 
-pub fn _process_staking_instruction(
-        program_id: &Pubkey, 
-        staking_acc: &AccountInfo,
-        owners_acc: &AccountInfo,
-        owner_token_acc: &AccountInfo,
-        pool_token_acc: &AccountInfo,
-        token_amount: u64,
-        now: UnixTimestamp,
-        duration: u16,
-        bump_seed: u8) -> ProgramResult {
-    if staking_acc.owner != program_id {
-        msg!("Staking account does not have the correct program id");
-        return Err(ProgramError::IncorrectProgramId);
-    }
-    // POOL = DjHnG6xbtxyT297XZWKyqxPsAngtoy9GkuehavoGUcY4
-    // let expected_pool_address = &Pubkey::create_program_address(
-    //     &[POOL_ADDRESS_SEED, &[bump_seed]],
-    //     program_id
-    // )?;
-    // if pool_token_acc.key != expected_pool_address {
-    //     msg!("Wrong pool address. Expected {} but got {}", expected_pool_address, pool_token_acc.key);
-    //     return Err(ProgramError::InvalidAccountData);
-    // }
-    let expected_pool_address = pool_token_acc.key;
-    // Остальные проверки касательно токенов выполнятся самой токен-программой.
-
-    if ALLOWED_DURATIONS_DAYS.iter().position(|&allowed_duration| allowed_duration == duration).is_none() {
-        msg!("Selected duration {} is not allowed", duration);
-        return Err(ProgramError::InvalidInstructionData);
+/// Rewards of one stream accrued by a position
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamPosition {
+    pub stream_id: u64,                // RewardStream.id, 0 - слот пуст
+    pub accrued_until: UnixTimestamp,
+    pub unclaimed: u64,
+}
+const STREAM_POSITION_SIZE: usize = 8 + 8 + 8;
+
+impl StakingAccount {
+    /// Zeroes the account data and leaves only the closed mark
+    pub fn wipe(staking_acc: &AccountInfo) -> ProgramResult {
+        let mut data = staking_acc.data.borrow_mut();
+        data.fill(0);
+        let mut staking_info = StakingAccount::try_from_slice(&data)?;
+        staking_info.closed = true;
+        staking_info.serialize(&mut &mut data[..])?;
+        Ok(())
     }
-    let mut staking_info = StakingAccount::try_from_slice(&staking_acc.data.borrow())?;
-    if staking_info.initialized { 
-        return Err(ProgramError::AccountAlreadyInitialized);
+
+    /// Interest accrued since the last withdraw, moves last_withdraw_date to the last full allocation period.
+    /// Periods after the end of the lock accrue according to post_maturity_accrual.
+    pub fn accrue_interest(&mut self, now: UnixTimestamp) -> Result<u64, ProgramError> {
+        if now < self.last_withdraw_date {
+            msg!("Staking account was created in the future?!");
+            return Err(StakingError::InvalidTimestamp.into());
+        }
+        let intervals_from_last_withdrawal: u64 = u64::try_from((now - self.last_withdraw_date) / (INTEREST_ALLOCATION_PERIOD_SECONDS as i64)).or(Err(StakingError::InvalidTimestamp))?;
+        // Период, внутри которого закончился лок, уже считается после окончания.
+        let locked_intervals = u64::try_from((self.lock_end() - self.last_withdraw_date).max(0) / (INTEREST_ALLOCATION_PERIOD_SECONDS as i64))
+            .or(Err(StakingError::InvalidTimestamp))?
+            .min(intervals_from_last_withdrawal);
+        let matured_intervals = intervals_from_last_withdrawal - locked_intervals;
+        self.last_withdraw_date += i64::try_from(INTEREST_ALLOCATION_PERIOD_SECONDS * intervals_from_last_withdrawal).or(Err(StakingError::MathOverflow))?;
+        let matured_interest = match self.post_maturity_accrual {
+            PostMaturityAccrual::FullRate => self.calculate_interest(matured_intervals)?,
+            PostMaturityAccrual::Stop => 0,
+            PostMaturityAccrual::Flexible => {
+                self.interest_at_rate(self.flexible_interest_numerator, self.flexible_interest_denominator, matured_intervals)?
+            },
+        };
+        self.calculate_interest(locked_intervals)?
+            .checked_add(matured_interest)
+            .ok_or_else(|| StakingError::MathOverflow.into())
     }
 
-    staking_info.initialized = true;
-    staking_info.holder = *owners_acc.key;
-    staking_info.created = now;
-    staking_info.token_amount = token_amount;
-    staking_info.last_withdraw_date = now;
-    staking_info.duration = duration;
-    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
-    Ok(())
-}
+    pub fn lock_end(&self) -> UnixTimestamp {
+        self.lock_start + i64::from(self.duration) * 24 * 3600
+    }
 
-pub fn _process_withdraw_interest_instruction(
-    program_id: &Pubkey,
-    staking_acc: &AccountInfo,
-    owners_acc: &AccountInfo,
-    owner_token_acc: &AccountInfo,
-    pool_token_acc: &AccountInfo,
-    now: UnixTimestamp,
-    bump_seed: u8,
-    withdraw_strategy: WithdrawStrategy,
-) -> Result<u64, ProgramError> {
-    if staking_acc.owner != program_id {
-        msg!("Staking account does not have the correct program id");
-        return Err(ProgramError::IncorrectProgramId);
+    pub fn lock_expired(&self, now: UnixTimestamp) -> bool {
+        now >= self.lock_end()
     }
-    let mut staking_info = StakingAccount::try_from_slice(&staking_acc.data.borrow())?;
-    if !staking_info.initialized {
-        return Err(ProgramError::AccountAlreadyInitialized);
+
+    /// Worst case interest the pool owes to the position: carried interest and the full rate until the end of the lock.
+    /// Interest after the end of the lock is not promised and is paid from the free reserve.
+    pub fn interest_liability(&self) -> Result<u64, ProgramError> {
+        let locked_intervals = u64::try_from((self.lock_end() - self.last_withdraw_date).max(0) / (INTEREST_ALLOCATION_PERIOD_SECONDS as i64))
+            .or(Err(StakingError::InvalidTimestamp))?;
+        self.calculate_interest(locked_intervals)?
+            .checked_add(self.extra_not_withdrawn_tokens)
+            .ok_or_else(|| StakingError::MathOverflow.into())
     }
-    if &staking_info.holder != owners_acc.key {
-        msg!("Staking account can not be withdrawn to someone else");
-        return Err(ProgramError::InvalidAccountData);
+
+    /// Part of the principal kept by the pool on early unstake
+    pub fn early_unstake_penalty(&self) -> u64 {
+        // bps не больше 10000, поэтому штраф не больше token_amount и влезает в u64.
+        (u128::from(self.token_amount) * u128::from(self.early_unstake_penalty_bps) / u128::from(BPS_DENOMINATOR)) as u64
     }
-    if !owners_acc.is_signer {
-        msg!("Withdraw instruction must be signed, otherwise, even the money is not stolen, you are loosing a chance to get compound interest");
-        return Err(ProgramError::InvalidAccountData);
+
+    /// Interest for `intervals` allocation periods at the rate locked in at stake time
+    pub fn calculate_interest(&self, intervals: u64) -> Result<u64, ProgramError> {
+        self.interest_at_rate(self.interest_numerator, self.interest_denominator, intervals)
     }
-    if now < staking_info.last_withdraw_date {
-        msg!("Staking account was created in the future?!");
-        return Err(ProgramError::InvalidAccountData);
+
+    fn interest_at_rate(&self, numerator: u64, denominator: u64, intervals: u64) -> Result<u64, ProgramError> {
+        let interest = u128::from(self.token_amount) * u128::from(numerator) * u128::from(intervals)
+            / (u128::from(denominator) * u128::from(ALLOCATION_PERDIODS_PER_YEAR));
+        Ok(u64::try_from(interest).or(Err(StakingError::MathOverflow))?)
     }
-    
-    let mut interest_index = ALLOWED_DURATIONS_DAYS.len();
-    for i in 0..ALLOWED_DURATIONS_DAYS.len() {
-        if ALLOWED_DURATIONS_DAYS[i] == staking_info.duration {
-            interest_index = i;
+
+    /// Moves rewards of all streams up to `now` into unclaimed, must be called before principal or tier changes.
+    /// Slots of removed or replaced streams are reset, a new stream accrues from its start or from the stake.
+    pub fn accrue_stream_rewards(&mut self, streams: &[RewardStream], now: UnixTimestamp) -> ProgramResult {
+        let (created, duration, token_amount) = (self.created, self.duration, self.token_amount);
+        for (position, stream) in self.reward_streams.iter_mut().zip(streams) {
+            if stream.id == 0 {
+                *position = StreamPosition::default();
+                continue;
+            }
+            if position.stream_id != stream.id {
+                *position = StreamPosition { stream_id: stream.id, accrued_until: stream.start.max(created), unclaimed: 0 };
+            }
+            let from = position.accrued_until.max(stream.start);
+            let to = now.min(stream.end);
+            if to > from {
+                if let Some(rate) = stream.rates.iter().find(|rate| rate.duration != 0 && rate.duration == duration) {
+                    let reward = u128::from(token_amount) * u128::from(rate.numerator) * ((to - from) as u128)
+                        / (u128::from(rate.denominator) * u128::from(SECONDS_PER_YEAR));
+                    let reward = u64::try_from(reward).or(Err(StakingError::MathOverflow))?;
+                    position.unclaimed = position.unclaimed.checked_add(reward).ok_or(StakingError::MathOverflow)?;
+                }
+            }
+            position.accrued_until = position.accrued_until.max(now);
         }
+        Ok(())
     }
-    if interest_index == ALLOWED_DURATIONS_DAYS.len() {
-        msg!("Duration {} is not supported", staking_info.duration);
-        return Err(ProgramError::InvalidAccountData);
+
+    /// Interest debt of the pool or unclaimed stream rewards, such a position is not wiped
+    fn owed_to_holder(&self) -> bool {
+        self.extra_not_withdrawn_tokens > 0 || self.reward_streams.iter().any(|position| position.unclaimed > 0)
     }
 
-    let intervals_from_last_withdrawal: u64 = u64::try_from((now - staking_info.last_withdraw_date) / (INTEREST_ALLOCATION_PERIOD_SECONDS as i64)).or(Err(ProgramError::InvalidAccountData))?;
-    staking_info.last_withdraw_date += i64::try_from(INTEREST_ALLOCATION_PERIOD_SECONDS * intervals_from_last_withdrawal).or(Err(ProgramError::InvalidAccountData))?;
-    let accumulated_interest = staking_info.token_amount * INTEREST_NOMITATORS_PER_ALLOCATION_PERIOD[interest_index] * intervals_from_last_withdrawal / INTEREST_DENOMITATORS_PER_ALLOCATION_PERIOD[interest_index];
+    fn snapshot_tier(&mut self, tier: &Tier) {
+        self.duration = tier.duration;
+        self.interest_numerator = tier.interest_numerator;
+        self.interest_denominator = tier.interest_denominator;
+        self.early_unstake_penalty_bps = tier.early_unstake_penalty_bps;
+        self.early_unstake_forfeits_interest = tier.early_unstake_forfeits_interest;
+        self.post_maturity_accrual = tier.post_maturity_accrual;
+        self.flexible_interest_numerator = tier.flexible_interest_numerator;
+        self.flexible_interest_denominator = tier.flexible_interest_denominator;
+    }
+}
 
-    match withdraw_strategy {
-        WithdrawStrategy::InterestOnly => {
-            staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
-            Ok(accumulated_interest)
-        },
-        WithdrawStrategy::Compound => {
-            staking_info.token_amount += accumulated_interest;
-            staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
-            Ok(0)
-        },
-        WithdrawStrategy::CloseAccount => {
-            if ((now - staking_info.created) as u64) / (3600u64 * 24u64) < staking_info.duration.into() {
-                Err(ProgramError::InvalidInstructionData)
-            } else {
-                let total_to_withdraw = staking_info.token_amount + accumulated_interest;
-                staking_info.token_amount = 0;
-                staking_info.last_withdraw_date = now;
-                staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
-                Ok(total_to_withdraw)
-            }
-        }
+/// How a position accrues after the end of its lock until it is closed or renewed
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PostMaturityAccrual {
+    FullRate, // как во время лока
+    Stop,     // ничего не начисляется
+    Flexible, // по ставке flexible_interest_numerator / flexible_interest_denominator
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for PostMaturityAccrual {
+    fn default() -> Self {
+        PostMaturityAccrual::FullRate
     }
 }
 
-// Declare and export the program's entrypoint
-entrypoint!(process_instruction);
+/// What the pool does when the reward reserve can not pay the interest in full
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum InsolvencyPolicy {
+    Revert,    // инструкция падает с InsufficientRewardReserve
+    FirstCome, // платим сколько есть в резерве, остаток в долг
+    ProRata,   // платим долю резерва пропорционально своим процентам среди всех обязательств пула, остаток в долг
+}
 
-// Program entrypoint's implementation
-pub fn process_instruction(
-    program_id: &Pubkey, // Public key of the account the hello world program was loaded into
-    accounts: &[AccountInfo], // The account to say hello to
-    _instruction_data: &[u8], // Ignored, all helloworld instructions are hellos
-) -> ProgramResult {
-    use solana_program::sysvar::Sysvar;
-    let instruction = Instruction::unpack(_instruction_data)?;
-    let account_info_iter = &mut accounts.iter();
-    match instruction {
-        Instruction::Stake { duration, amount, bump_seed } => {
-            let staking_account = next_account_info(account_info_iter)?;
-            let owner_account = next_account_info(account_info_iter)?;
-            let owner_token_account = next_account_info(account_info_iter)?;
-            let pool_token_account = next_account_info(account_info_iter)?;
-            let token_program = next_account_info(account_info_iter)?;
-            _process_staking_instruction(
-                program_id,
-                staking_account,
-                owner_account,
-                owner_token_account,
-                pool_token_account,
-                amount,
-                Clock::get()?.unix_timestamp,
-                duration,
-                bump_seed)?;
-            if token_program.key != &SPL_TOKEN_PROGRAM_ID {
-                return Err(ProgramError::IncorrectProgramId);
-            }
-            // let expected_pool_address = &Pubkey::create_program_address(
-            //     &[POOL_ADDRESS_SEED, &[bump_seed]],
-            //     program_id
-            // )?;
-            let ix = spl_token::instruction::transfer(
-                &SPL_TOKEN_PROGRAM_ID,
-                owner_token_account.key,
-                pool_token_account.key,
-                owner_account.key,
-                &[&owner_account.key],
-                amount,
-            )?;
-            msg!("Signer info: {}", owner_account.key);
-            msg!("Owner info: {}", Pubkey::new(&owner_token_account.data.borrow()[32..64]));
-            invoke(&ix, &[
-                owner_token_account.clone(),
-                pool_token_account.clone(),
-                owner_account.clone(),
-                token_program.clone(),
-            ])?;
-        },
-        Instruction::WithdrawInterest {bump_seed} | Instruction::Compound {bump_seed} | Instruction::CloseAccount {bump_seed} => {
-            let staking_account = next_account_info(account_info_iter)?;
-            let owner_account = next_account_info(account_info_iter)?;
-            let owner_token_account = next_account_info(account_info_iter)?;
-            let pool_token_account = next_account_info(account_info_iter)?;
-            let token_program = next_account_info(account_info_iter)?;
-            let pool_manager_account = next_account_info(account_info_iter)?;
-            let amount = _process_withdraw_interest_instruction(
-                program_id,
-                staking_account,
-                owner_account,
-                owner_token_account,
-                pool_token_account,
-                Clock::get()?.unix_timestamp,
-                bump_seed,
-                match instruction {
-                    Instruction::WithdrawInterest{..} => WithdrawStrategy::InterestOnly,
-                    Instruction::Compound{..} => WithdrawStrategy::Compound,
-                    Instruction::CloseAccount{..} => WithdrawStrategy::CloseAccount,
-                    _ => unreachable!(),
-                })?;
-            if token_program.key != &SPL_TOKEN_PROGRAM_ID {
-                return Err(ProgramError::IncorrectProgramId);
-            }
-            let pool_owner = &Pubkey::create_program_address(
-                &[POOL_ADDRESS_SEED, &[bump_seed]],
-                program_id
-            )?;
-            let ix = spl_token::instruction::transfer(
-                &SPL_TOKEN_PROGRAM_ID,
-                pool_token_account.key,
-                owner_token_account.key,
-                pool_owner,
-                &[&pool_owner],
-                amount,
-            )?;
-            msg!("Invoke signed. Pool owner={}. Sending {} from pool", pool_owner, amount);
-            invoke_signed(&ix, &[
-                pool_manager_account.clone(),
-                pool_token_account.clone(),
-                owner_token_account.clone(),
-                owner_account.clone(),
-                token_program.clone(),
-            ], &[&[&[0x50, 0x00, 0x00, 0x10, 0x20, 0xad, 0x35][..], &[bump_seed]]])?;
+// #[default] на вариантах enum появился только в rustc 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for InsolvencyPolicy {
+    fn default() -> Self {
+        InsolvencyPolicy::Revert
+    }
+}
+
+/// Lock period with its annual interest rate
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct Tier {
+    pub duration: u16,              // период лока в днях
+    pub interest_numerator: u64,    // годовой процент = interest_numerator / interest_denominator
+    pub interest_denominator: u64,
+    pub retired: bool,              // новые стейки запрещены, старые продолжают начисляться
+    pub early_unstake_penalty_bps: u16,        // доля тела в базисных пунктах, которую оставляет себе пул
+    pub early_unstake_forfeits_interest: bool, // при досрочном выходе набежавшие проценты не выплачиваются
+    pub post_maturity_accrual: PostMaturityAccrual, // начисление после окончания лока
+    pub flexible_interest_numerator: u64,  // годовой процент после окончания лока для Flexible
+    pub flexible_interest_denominator: u64,
+}
+const TIER_SIZE: usize = 2 + 8 + 8 + 1 + 2 + 1 + 1 + 8 + 8;
+
+// Тир из версий инструкций без штрафа и политики после лока.
+impl Default for Tier {
+    fn default() -> Self {
+        Tier {
+            duration: 0,
+            interest_numerator: 0,
+            interest_denominator: 0,
+            retired: false,
+            early_unstake_penalty_bps: 0,
+            early_unstake_forfeits_interest: true,
+            post_maturity_accrual: PostMaturityAccrual::FullRate,
+            flexible_interest_numerator: 0,
+            flexible_interest_denominator: 0,
         }
     }
+}
+pub const MAX_TIERS: usize = 8;
 
-    Ok(())
+/// Annual rate of a reward stream for positions of one tier
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamRate {
+    pub duration: u16,    // тир, 0 - слот пуст
+    pub numerator: u64,   // годовых токенов стрима на токен тела = numerator / denominator
+    pub denominator: u64,
 }
+const STREAM_RATE_SIZE: usize = 2 + 8 + 8;
 
-// Sanity tests
-#[cfg(test)]
-mod test {
-    use super::*;
-    use solana_program::clock::Epoch;
-    use std::mem;
+/// Reward campaign paying its own token to positions between start and end
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct RewardStream {
+    pub id: u64,                  // уникален в пуле, 0 - слот свободен
+    pub mint: Pubkey,
+    pub vault: Pubkey,            // токен-аккаунт стрима, им владеет PDA от POOL_ADDRESS_SEED
+    pub start: UnixTimestamp,
+    pub end: UnixTimestamp,
+    pub rates: [StreamRate; MAX_TIERS],
+}
+const REWARD_STREAM_SIZE: usize = 8 + 32 + 32 + 8 + 8 + MAX_TIERS * STREAM_RATE_SIZE;
+pub const MAX_REWARD_STREAMS: usize = 4;
+
+/// Pool economics, stored in the PDA of POOL_CONFIG_SEED
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct PoolConfig {
+    pub initialized: bool,
+    pub admin: Pubkey,           // кто может менять настройки пула
+    pub stake_mint: Pubkey,      // токен, который стейкают
+    pub vault: Pubkey,           // токен-аккаунт пула, им владеет PDA от POOL_ADDRESS_SEED
+    pub bump: u8,                // канонический bump адреса конфига
+    pub authority_bump: u8,      // канонический bump владельца vault
+    pub treasury: Pubkey,        // куда уходят штрафы за досрочный выход, по умолчанию остаются в vault
+    pub keeper_tip_bps: u16,     // доля капитализируемых процентов, которую получает кипер за CrankCompound
+    pub reward_mint: Pubkey,     // токен, в котором платятся проценты, по умолчанию stake_mint
+    pub reward_vault: Pubkey,    // откуда платятся проценты, по умолчанию из vault
+    pub total_principal: u64,    // сумма тел всех открытых позиций
+    pub reward_reserve: u64,     // внесено через FundRewards и еще не выплачено, проценты платятся только отсюда
+    pub interest_liability: u64, // сколько процентов в худшем случае обещано открытым позициям, не больше reward_reserve
+    pub insolvency_policy: InsolvencyPolicy, // больше reward_reserve обязательства становятся только из-за долгов
+    pub paused: u8,              // флаги PAUSE_*, выставляет админ
+    pub last_reward_stream_id: u64,
+    pub reward_streams: [RewardStream; MAX_REWARD_STREAMS],
+    pub tiers: Vec<Tier>,        // не больше MAX_TIERS, отсортированы по duration
+}
+pub const POOL_CONFIG_SIZE: usize = 1 + 32 + 32 + 32 + 1 + 1 + 32 + 2 + 32 + 32 + 8 + 8 + 8 + 1 + 1 + 8 + MAX_REWARD_STREAMS * REWARD_STREAM_SIZE
+    + 4 + MAX_TIERS * TIER_SIZE;
+
+impl PoolConfig {
+    /// Reads the config and makes sure it is the real one, not an account forged by the caller.
+    pub fn load(program_id: &Pubkey, pool_config_acc: &AccountInfo) -> Result<Self, ProgramError> {
+        if pool_config_acc.owner != program_id {
+            msg!("Pool config does not have the correct program id");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if pool_config_acc.data_len() != POOL_CONFIG_SIZE {
+            msg!("Pool config has wrong size");
+            return Err(StakingError::InvalidPoolConfig.into());
+        }
+        // Аккаунт фиксированного размера, хвост после tiers заполнен нулями,
+        // поэтому try_from_slice здесь не подходит.
+        let pool_config = Self::deserialize(&mut &pool_config_acc.data.borrow()[..])?;
+        if !pool_config.initialized {
+            msg!("Pool is not initialized");
+            return Err(StakingError::PoolNotInitialized.into());
+        }
+        let expected_address = Pubkey::create_program_address(
+            &[POOL_CONFIG_SEED, &[pool_config.bump]],
+            program_id,
+        )?;
+        if pool_config_acc.key != &expected_address {
+            msg!("Wrong pool config address. Expected {} but got {}", expected_address, pool_config_acc.key);
+            return Err(StakingError::InvalidPoolConfig.into());
+        }
+        Ok(pool_config)
+    }
+
+    pub fn save(&self, pool_config_acc: &AccountInfo) -> ProgramResult {
+        self.serialize(&mut &mut pool_config_acc.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    pub fn find_tier(&self, duration: u16) -> Option<&Tier> {
+        self.tiers
+            .binary_search_by_key(&duration, |tier| tier.duration)
+            .ok()
+            .map(|index| &self.tiers[index])
+    }
+
+    pub fn find_tier_mut(&mut self, duration: u16) -> Option<&mut Tier> {
+        match self.tiers.binary_search_by_key(&duration, |tier| tier.duration) {
+            Ok(index) => Some(&mut self.tiers[index]),
+            Err(_) => None,
+        }
+    }
+
+    /// Pool manager, the owner of the vault and signer of transfers out of it
+    pub fn pool_authority(&self, program_id: &Pubkey) -> Result<Pubkey, ProgramError> {
+        Ok(Pubkey::create_program_address(&[POOL_ADDRESS_SEED, &[self.authority_bump]], program_id)
+            .or(Err(StakingError::InvalidPoolConfig))?)
+    }
+
+    /// Pool manager account must be the PDA derived with the stored bump, it signs transfers out of the vault.
+    pub fn check_pool_authority(&self, program_id: &Pubkey, pool_manager_acc: &AccountInfo) -> Result<Pubkey, ProgramError> {
+        let pool_authority = self.pool_authority(program_id)?;
+        if pool_manager_acc.key != &pool_authority {
+            msg!("Wrong pool manager. Expected {} but got {}", pool_authority, pool_manager_acc.key);
+            return Err(StakingError::InvalidPoolAuthority.into());
+        }
+        Ok(pool_authority)
+    }
+
+    /// Pool token account must be the configured vault, otherwise anyone could "stake" into their own account.
+    pub fn check_vault(&self, program_id: &Pubkey, pool_token_acc: &AccountInfo) -> ProgramResult {
+        if pool_token_acc.key != &self.vault {
+            msg!("Wrong pool address. Expected {} but got {}", self.vault, pool_token_acc.key);
+            return Err(StakingError::InvalidPoolVault.into());
+        }
+        check_pool_vault(pool_token_acc, &self.stake_mint, &self.pool_authority(program_id)?)
+    }
+
+    /// Interest is paid from reward_vault, possibly in another mint, and can't become principal.
+    pub fn has_reward_vault(&self) -> bool {
+        self.reward_vault != Pubkey::default()
+    }
+
+    pub fn check_reward_vault(&self, program_id: &Pubkey, reward_vault_acc: &AccountInfo) -> ProgramResult {
+        if reward_vault_acc.key != &self.reward_vault {
+            msg!("Wrong reward vault. Expected {} but got {}", self.reward_vault, reward_vault_acc.key);
+            return Err(StakingError::InvalidPoolVault.into());
+        }
+        check_pool_vault(reward_vault_acc, &self.reward_mint, &self.pool_authority(program_id)?)
+    }
+
+    /// Account interest is paid from: the reward vault, or the vault for pools without one.
+    pub fn check_interest_vault(&self, program_id: &Pubkey, interest_vault_acc: &AccountInfo) -> ProgramResult {
+        if self.has_reward_vault() {
+            self.check_reward_vault(program_id, interest_vault_acc)
+        } else {
+            self.check_vault(program_id, interest_vault_acc)
+        }
+    }
+
+    pub fn interest_mint(&self) -> &Pubkey {
+        if self.has_reward_vault() { &self.reward_mint } else { &self.stake_mint }
+    }
+
+    // В пуле с reward vault штраф без казны остался бы в vault, не попав ни в резерв, ни в тело.
+    fn check_penalty_receiver(&self) -> ProgramResult {
+        if self.has_reward_vault() && self.treasury == Pubkey::default() {
+            msg!("Pool with a reward vault needs a treasury for early unstake penalties");
+            return Err(StakingError::TreasuryRequired.into());
+        }
+        Ok(())
+    }
+
+    fn add_principal(&mut self, amount: u64) -> ProgramResult {
+        self.total_principal = self.total_principal.checked_add(amount).ok_or(StakingError::MathOverflow)?;
+        Ok(())
+    }
+
+    fn remove_principal(&mut self, amount: u64) -> ProgramResult {
+        self.total_principal = self.total_principal.checked_sub(amount).ok_or(StakingError::MathOverflow)?;
+        Ok(())
+    }
+
+    // Проценты списываются с резерва до выплаты, тела чужих позиций на них не тратятся.
+    // Обязательства позиции к этому моменту уже пересчитаны, поэтому платим из свободной части резерва.
+    fn take_from_reward_reserve(&mut self, amount: u64) -> ProgramResult {
+        let free_reserve = self.reward_reserve.saturating_sub(self.interest_liability);
+        if amount > free_reserve {
+            msg!("Free reward reserve {} does not cover interest of {}", free_reserve, amount);
+            return Err(StakingError::InsufficientRewardReserve.into());
+        }
+        self.reward_reserve -= amount;
+        Ok(())
+    }
+
+    /// Replaces the share of the position in interest_liability with its current worst case interest.
+    fn update_interest_liability(&mut self, staking_info: &mut StakingAccount) -> ProgramResult {
+        let reserved_interest = staking_info.interest_liability()?;
+        self.interest_liability = self.interest_liability
+            .checked_sub(staking_info.reserved_interest)
+            .and_then(|liability| liability.checked_add(reserved_interest))
+            .ok_or(StakingError::MathOverflow)?;
+        staking_info.reserved_interest = reserved_interest;
+        Ok(())
+    }
+
+    fn release_interest_liability(&mut self, staking_info: &mut StakingAccount) -> ProgramResult {
+        self.interest_liability = self.interest_liability.checked_sub(staking_info.reserved_interest).ok_or(StakingError::MathOverflow)?;
+        staking_info.reserved_interest = 0;
+        Ok(())
+    }
+
+    /// Takes the interest of the position from the reserve according to insolvency_policy, returns the part to pay now.
+    /// The rest is owed to the holder in extra_not_withdrawn_tokens and paid by the next withdraw.
+    fn pay_interest(&mut self, staking_info: &mut StakingAccount, amount: u64) -> Result<u64, ProgramError> {
+        let free_reserve = self.reward_reserve.saturating_sub(self.interest_liability);
+        let paid = match self.insolvency_policy {
+            _ if amount <= free_reserve => amount,
+            InsolvencyPolicy::Revert => return self.take_from_reward_reserve(amount).map(|_| amount),
+            InsolvencyPolicy::FirstCome => amount.min(self.reward_reserve),
+            // Резерв делится между всеми обязательствами пула, включая долги и обещания до конца локов.
+            InsolvencyPolicy::ProRata => {
+                (u128::from(amount) * u128::from(self.reward_reserve) / (u128::from(self.interest_liability) + u128::from(amount))) as u64
+            },
+        };
+        self.reward_reserve -= paid;
+        if paid < amount {
+            msg!("Reward reserve is short, {} tokens of interest are owed to the holder", amount - paid);
+            staking_info.extra_not_withdrawn_tokens = staking_info.extra_not_withdrawn_tokens
+                .checked_add(amount - paid).ok_or(StakingError::MathOverflow)?;
+            self.update_interest_liability(staking_info)?;
+        }
+        Ok(paid)
+    }
+
+    // Новые обещания принимаются, только если их покрывает уже внесенный резерв.
+    fn check_interest_covered(&self) -> ProgramResult {
+        if self.interest_liability > self.reward_reserve {
+            msg!("Interest liability {} exceeds the reward reserve {}", self.interest_liability, self.reward_reserve);
+            return Err(StakingError::InterestNotCovered.into());
+        }
+        Ok(())
+    }
+
+    fn check_not_paused(&self, flags: u8) -> ProgramResult {
+        if self.paused & flags != 0 {
+            msg!("Instruction is paused, pool pause flags {}", self.paused);
+            return Err(StakingError::InstructionPaused.into());
+        }
+        Ok(())
+    }
+
+    fn check_compound_allowed(&self) -> ProgramResult {
+        if self.has_reward_vault() {
+            msg!("Interest is paid from the reward vault in {} and can not be compounded", self.reward_mint);
+            return Err(StakingError::CompoundNotSupported.into());
+        }
+        Ok(())
+    }
+
+    /// Loads the config for an admin instruction, the admin must sign it.
+    pub fn load_as_admin(program_id: &Pubkey, pool_config_acc: &AccountInfo, admin_acc: &AccountInfo) -> Result<Self, ProgramError> {
+        let pool_config = Self::load(program_id, pool_config_acc)?;
+        if &pool_config.admin != admin_acc.key {
+            msg!("Only pool admin {} can change the pool", pool_config.admin);
+            return Err(StakingError::NotPoolAdmin.into());
+        }
+        if !admin_acc.is_signer {
+            msg!("Admin instruction must be signed");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(pool_config)
+    }
+}
+
+fn unpack_token_account(token_acc: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+    if token_acc.owner != &SPL_TOKEN_PROGRAM_ID {
+        msg!("Token account {} does not belong to the token program", token_acc.key);
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    TokenAccount::unpack(&token_acc.data.borrow()).map_err(|_| {
+        msg!("{} is not an initialized token account", token_acc.key);
+        StakingError::InvalidTokenAccount.into()
+    })
+}
+
+fn check_pool_vault(pool_token_acc: &AccountInfo, stake_mint: &Pubkey, pool_authority: &Pubkey) -> ProgramResult {
+    let vault = unpack_token_account(pool_token_acc)?;
+    if &vault.owner != pool_authority {
+        msg!("Pool token account is owned by {} instead of the pool manager {}", vault.owner, pool_authority);
+        return Err(StakingError::VaultNotOwnedByPool.into());
+    }
+    if &vault.mint != stake_mint {
+        msg!("Pool token account holds {} instead of {}", vault.mint, stake_mint);
+        return Err(StakingError::MintMismatch.into());
+    }
+    Ok(())
+}
+
+// Token account the holder stakes from, the pool vault must be checked before.
+fn check_holder_token_account(
+    pool_config: &PoolConfig,
+    owners_acc: &AccountInfo,
+    owner_token_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+) -> ProgramResult {
+    if owner_token_acc.key == pool_token_acc.key {
+        msg!("Can not stake from the pool vault into itself");
+        return Err(StakingError::SelfTransfer.into());
+    }
+    let owner_token = unpack_token_account(owner_token_acc)?;
+    if &owner_token.owner != owners_acc.key {
+        msg!("Token account {} belongs to {} and not to the holder", owner_token_acc.key, owner_token.owner);
+        return Err(StakingError::TokenAccountNotOwnedByHolder.into());
+    }
+    if owner_token.mint != pool_config.stake_mint {
+        msg!("Token account holds {} instead of {}", owner_token.mint, pool_config.stake_mint);
+        return Err(StakingError::MintMismatch.into());
+    }
+    // Баланс и делегирование проверит сама токен-программа при переводе.
+    Ok(())
+}
+
+fn validate_tier_rate(tier: &Tier) -> ProgramResult {
+    if tier.duration == 0 || tier.interest_denominator == 0 {
+        msg!("Tier {} days with rate {}/{} is malformed", tier.duration, tier.interest_numerator, tier.interest_denominator);
+        return Err(StakingError::InvalidTier.into());
+    }
+    Ok(())
+}
+
+fn validate_tier_penalty(tier: &Tier) -> ProgramResult {
+    if tier.early_unstake_penalty_bps > BPS_DENOMINATOR {
+        msg!("Tier {} days early unstake penalty {} bps is more than the whole stake", tier.duration, tier.early_unstake_penalty_bps);
+        return Err(StakingError::InvalidTier.into());
+    }
+    Ok(())
+}
+
+fn validate_tier_post_maturity(tier: &Tier) -> ProgramResult {
+    if tier.post_maturity_accrual == PostMaturityAccrual::Flexible && tier.flexible_interest_denominator == 0 {
+        msg!("Tier {} days flexible rate {}/{} is malformed", tier.duration, tier.flexible_interest_numerator, tier.flexible_interest_denominator);
+        return Err(StakingError::InvalidTier.into());
+    }
+    Ok(())
+}
+
+fn validate_tiers(tiers: &[Tier]) -> ProgramResult {
+    if tiers.is_empty() || tiers.len() > MAX_TIERS {
+        msg!("Pool must have from 1 to {} tiers, got {}", MAX_TIERS, tiers.len());
+        return Err(StakingError::TooManyTiers.into());
+    }
+    for (i, tier) in tiers.iter().enumerate() {
+        validate_tier_rate(tier)?;
+        validate_tier_penalty(tier)?;
+        validate_tier_post_maturity(tier)?;
+        if tiers[..i].iter().any(|other| other.duration == tier.duration) {
+            msg!("Duplicate tier {} days", tier.duration);
+            return Err(StakingError::TierAlreadyExists.into());
+        }
+    }
+    Ok(())
+}
+
+// Начало ProgramData в bincode: тег варианта u32, slot u64, Option<Pubkey> с тегом u8.
+const PROGRAM_DATA_HEADER_SIZE: usize = 4 + 8 + 1 + 32;
+
+// Пул создает только тот, кто может обновлять программу, иначе админом станет первый успевший.
+fn check_upgrade_authority(program_id: &Pubkey, program_data_acc: &AccountInfo, admin_acc: &AccountInfo) -> ProgramResult {
+    let (expected_program_data_address, _) = Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if program_data_acc.key != &expected_program_data_address {
+        msg!("Wrong program data address. Expected {} but got {}", expected_program_data_address, program_data_acc.key);
+        return Err(StakingError::NotUpgradeAuthority.into());
+    }
+    if program_data_acc.owner != &bpf_loader_upgradeable::id() {
+        msg!("Program data account must belong to the upgradeable loader");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let data = program_data_acc.data.borrow();
+    let is_program_data_with_authority = data.len() >= PROGRAM_DATA_HEADER_SIZE && data[..4] == [3, 0, 0, 0] && data[12] == 1;
+    if !is_program_data_with_authority || &data[13..PROGRAM_DATA_HEADER_SIZE] != admin_acc.key.as_ref() {
+        msg!("Pool can be initialized only by the upgrade authority of the program");
+        return Err(StakingError::NotUpgradeAuthority.into());
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn _process_initialize_pool_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    program_data_acc: &AccountInfo,
+    stake_mint_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+    bump: u8,
+    authority_bump: u8,
+    tiers: Vec<Tier>,
+    reward_accs: Option<(&AccountInfo, &AccountInfo)>,
+) -> ProgramResult {
+    if pool_config_acc.owner != program_id {
+        msg!("Pool config does not have the correct program id");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !admin_acc.is_signer {
+        msg!("Pool must be initialized by its admin");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_upgrade_authority(program_id, program_data_acc, admin_acc)?;
+    if stake_mint_acc.owner != &SPL_TOKEN_PROGRAM_ID {
+        msg!("Stake mint must belong to the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Mint::unpack(&stake_mint_acc.data.borrow()).or(Err(StakingError::InvalidTokenAccount))?;
+    let pool_authority = Pubkey::create_program_address(&[POOL_ADDRESS_SEED, &[authority_bump]], program_id)?;
+    check_pool_vault(pool_token_acc, stake_mint_acc.key, &pool_authority)?;
+    if let Some((reward_mint_acc, reward_vault_acc)) = reward_accs {
+        if reward_mint_acc.owner != &SPL_TOKEN_PROGRAM_ID {
+            msg!("Reward mint must belong to the token program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Mint::unpack(&reward_mint_acc.data.borrow()).or(Err(StakingError::InvalidTokenAccount))?;
+        if reward_vault_acc.key == pool_token_acc.key {
+            msg!("Reward vault must be separate from the pool token account");
+            return Err(StakingError::SelfTransfer.into());
+        }
+        check_pool_vault(reward_vault_acc, reward_mint_acc.key, &pool_authority)?;
+    }
+    validate_tiers(&tiers)?;
+    let mut tiers = tiers;
+    tiers.sort_by_key(|tier| tier.duration);
+    let mut pool_config = PoolConfig::deserialize(&mut &pool_config_acc.data.borrow()[..])?;
+    if pool_config.initialized {
+        return Err(StakingError::PoolAlreadyInitialized.into());
+    }
+
+    pool_config.initialized = true;
+    pool_config.admin = *admin_acc.key;
+    pool_config.stake_mint = *stake_mint_acc.key;
+    pool_config.vault = *pool_token_acc.key;
+    pool_config.bump = bump;
+    pool_config.authority_bump = authority_bump;
+    pool_config.treasury = Pubkey::default();
+    pool_config.keeper_tip_bps = 0;
+    let (reward_mint, reward_vault) = reward_accs
+        .map(|(reward_mint_acc, reward_vault_acc)| (*reward_mint_acc.key, *reward_vault_acc.key))
+        .unwrap_or_default();
+    pool_config.reward_mint = reward_mint;
+    pool_config.reward_vault = reward_vault;
+    pool_config.total_principal = 0;
+    pool_config.reward_reserve = 0;
+    pool_config.interest_liability = 0;
+    pool_config.insolvency_policy = InsolvencyPolicy::Revert;
+    pool_config.paused = 0;
+    pool_config.last_reward_stream_id = 0;
+    pool_config.reward_streams = [RewardStream::default(); MAX_REWARD_STREAMS];
+    pool_config.tiers = tiers;
+    // Казну задают только после создания пула.
+    if pool_config.tiers.iter().any(|tier| tier.early_unstake_penalty_bps > 0) {
+        pool_config.check_penalty_receiver()?;
+    }
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_add_tier_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    tier: Tier,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    validate_tier_rate(&tier)?;
+    validate_tier_penalty(&tier)?;
+    validate_tier_post_maturity(&tier)?;
+    if tier.early_unstake_penalty_bps > 0 {
+        pool_config.check_penalty_receiver()?;
+    }
+    if pool_config.tiers.len() >= MAX_TIERS {
+        msg!("Pool already has {} tiers", MAX_TIERS);
+        return Err(StakingError::TooManyTiers.into());
+    }
+    match pool_config.tiers.binary_search_by_key(&tier.duration, |tier| tier.duration) {
+        Ok(_) => {
+            msg!("Tier {} days already exists", tier.duration);
+            Err(StakingError::TierAlreadyExists.into())
+        },
+        Err(index) => {
+            msg!("Adding tier {} days with rate {}/{}", tier.duration, tier.interest_numerator, tier.interest_denominator);
+            pool_config.tiers.insert(index, tier);
+            pool_config.save(pool_config_acc)
+        }
+    }
+}
+
+pub fn _process_update_tier_rate_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    duration: u16,
+    interest_numerator: u64,
+    interest_denominator: u64,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    let tier = pool_config.find_tier_mut(duration).ok_or_else(|| {
+        msg!("Tier {} days does not exist", duration);
+        StakingError::TierNotFound
+    })?;
+    if tier.retired {
+        msg!("Tier {} days is retired", duration);
+        return Err(StakingError::TierRetired.into());
+    }
+    tier.interest_numerator = interest_numerator;
+    tier.interest_denominator = interest_denominator;
+    validate_tier_rate(tier)?;
+    msg!("Tier {} days rate is now {}/{}", duration, interest_numerator, interest_denominator);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_retire_tier_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    duration: u16,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    let tier = pool_config.find_tier_mut(duration).ok_or_else(|| {
+        msg!("Tier {} days does not exist", duration);
+        StakingError::TierNotFound
+    })?;
+    if tier.retired {
+        msg!("Tier {} days is already retired", duration);
+        return Err(StakingError::TierRetired.into());
+    }
+    tier.retired = true;
+    msg!("Tier {} days is retired", duration);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_set_tier_penalty_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    duration: u16,
+    early_unstake_penalty_bps: u16,
+    early_unstake_forfeits_interest: bool,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    if early_unstake_penalty_bps > 0 {
+        pool_config.check_penalty_receiver()?;
+    }
+    let tier = pool_config.find_tier_mut(duration).ok_or_else(|| {
+        msg!("Tier {} days does not exist", duration);
+        StakingError::TierNotFound
+    })?;
+    tier.early_unstake_penalty_bps = early_unstake_penalty_bps;
+    tier.early_unstake_forfeits_interest = early_unstake_forfeits_interest;
+    validate_tier_penalty(tier)?;
+    msg!("Tier {} days early unstake penalty is now {} bps, forfeits interest: {}", duration, early_unstake_penalty_bps, early_unstake_forfeits_interest);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_set_tier_post_maturity_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    duration: u16,
+    post_maturity_accrual: PostMaturityAccrual,
+    flexible_interest_numerator: u64,
+    flexible_interest_denominator: u64,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    let tier = pool_config.find_tier_mut(duration).ok_or_else(|| {
+        msg!("Tier {} days does not exist", duration);
+        StakingError::TierNotFound
+    })?;
+    tier.post_maturity_accrual = post_maturity_accrual;
+    tier.flexible_interest_numerator = flexible_interest_numerator;
+    tier.flexible_interest_denominator = flexible_interest_denominator;
+    validate_tier_post_maturity(tier)?;
+    msg!("Tier {} days post maturity accrual is now {:?}", duration, post_maturity_accrual);
+    pool_config.save(pool_config_acc)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn _process_add_reward_stream_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    mint_acc: &AccountInfo,
+    stream_vault_acc: &AccountInfo,
+    start: UnixTimestamp,
+    end: UnixTimestamp,
+    rates: Vec<StreamRate>,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    if start >= end || rates.is_empty() || rates.len() > MAX_TIERS {
+        msg!("Reward stream from {} to {} with {} rates is malformed", start, end, rates.len());
+        return Err(StakingError::InvalidRewardStream.into());
+    }
+    for (i, rate) in rates.iter().enumerate() {
+        if rate.duration == 0 || rate.denominator == 0 || rates[..i].iter().any(|other| other.duration == rate.duration) {
+            msg!("Reward stream rate for {} days {}/{} is malformed", rate.duration, rate.numerator, rate.denominator);
+            return Err(StakingError::InvalidRewardStream.into());
+        }
+    }
+    if mint_acc.owner != &SPL_TOKEN_PROGRAM_ID {
+        msg!("Stream mint must belong to the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Mint::unpack(&mint_acc.data.borrow()).or(Err(StakingError::InvalidTokenAccount))?;
+    if stream_vault_acc.key == &pool_config.vault {
+        msg!("Stream vault must be separate from the pool token account");
+        return Err(StakingError::SelfTransfer.into());
+    }
+    check_pool_vault(stream_vault_acc, mint_acc.key, &pool_config.pool_authority(program_id)?)?;
+    let id = pool_config.last_reward_stream_id.checked_add(1).ok_or(StakingError::MathOverflow)?;
+    let slot = pool_config.reward_streams.iter_mut().find(|stream| stream.id == 0).ok_or_else(|| {
+        msg!("Pool already has {} reward streams", MAX_REWARD_STREAMS);
+        StakingError::TooManyRewardStreams
+    })?;
+    let mut stream_rates = [StreamRate::default(); MAX_TIERS];
+    stream_rates[..rates.len()].copy_from_slice(&rates);
+    *slot = RewardStream { id, mint: *mint_acc.key, vault: *stream_vault_acc.key, start, end, rates: stream_rates };
+    pool_config.last_reward_stream_id = id;
+    msg!("Reward stream {} of {} runs until {}", id, mint_acc.key, end);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_remove_reward_stream_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    id: u64,
+    now: UnixTimestamp,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    let stream = pool_config.reward_streams.iter_mut().find(|stream| id != 0 && stream.id == id).ok_or_else(|| {
+        msg!("Reward stream {} does not exist", id);
+        StakingError::RewardStreamNotFound
+    })?;
+    // Холдерам дается время забрать награды после окончания кампании.
+    if now < stream.end.saturating_add(REWARD_CLAIM_WINDOW_SECONDS) {
+        msg!("Reward stream {} can be claimed until {}", id, stream.end.saturating_add(REWARD_CLAIM_WINDOW_SECONDS));
+        return Err(StakingError::RewardStreamActive.into());
+    }
+    *stream = RewardStream::default();
+    msg!("Reward stream {} is removed", id);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_set_treasury_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    treasury_acc: &AccountInfo,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    let treasury = unpack_token_account(treasury_acc)?;
+    if treasury.mint != pool_config.stake_mint {
+        msg!("Treasury holds {} instead of {}", treasury.mint, pool_config.stake_mint);
+        return Err(StakingError::MintMismatch.into());
+    }
+    if treasury_acc.key == &pool_config.vault {
+        msg!("Treasury can not be the pool token account");
+        return Err(StakingError::SelfTransfer.into());
+    }
+    pool_config.treasury = *treasury_acc.key;
+    msg!("Early unstake penalties go to {}", treasury_acc.key);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_set_keeper_tip_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    keeper_tip_bps: u16,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    if keeper_tip_bps > MAX_KEEPER_TIP_BPS {
+        msg!("Keeper tip {} bps is more than {} bps", keeper_tip_bps, MAX_KEEPER_TIP_BPS);
+        return Err(StakingError::InvalidKeeperTip.into());
+    }
+    pool_config.keeper_tip_bps = keeper_tip_bps;
+    msg!("Keeper tip is now {} bps", keeper_tip_bps);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_set_insolvency_policy_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    insolvency_policy: InsolvencyPolicy,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    pool_config.insolvency_policy = insolvency_policy;
+    msg!("Insolvency policy is now {:?}", insolvency_policy);
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_set_pause_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    admin_acc: &AccountInfo,
+    paused: u8,
+) -> ProgramResult {
+    let mut pool_config = PoolConfig::load_as_admin(program_id, pool_config_acc, admin_acc)?;
+    if paused & !PAUSE_ALL != 0 {
+        msg!("Unknown pause flags {}", paused);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if pool_config.paused != paused {
+        msg!("Pool pause flags changed from {} to {} by {}", pool_config.paused, paused, admin_acc.key);
+    }
+    pool_config.paused = paused;
+    pool_config.save(pool_config_acc)
+}
+
+/// Anyone can top up the reserve interest is paid from, the transfer is made by the caller.
+pub fn _process_fund_rewards_instruction(
+    program_id: &Pubkey,
+    pool_config_acc: &AccountInfo,
+    funder_acc: &AccountInfo,
+    funder_token_acc: &AccountInfo,
+    interest_vault_acc: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    if !funder_acc.is_signer {
+        msg!("Funder {} must sign the deposit", funder_acc.key);
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if amount == 0 {
+        msg!("Nothing to deposit");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_interest_vault(program_id, interest_vault_acc)?;
+    if funder_token_acc.key == interest_vault_acc.key {
+        msg!("Can not fund the pool from its own vault");
+        return Err(StakingError::SelfTransfer.into());
+    }
+    let funder_token = unpack_token_account(funder_token_acc)?;
+    if &funder_token.mint != pool_config.interest_mint() {
+        msg!("Token account holds {} instead of {}", funder_token.mint, pool_config.interest_mint());
+        return Err(StakingError::MintMismatch.into());
+    }
+    pool_config.reward_reserve = pool_config.reward_reserve.checked_add(amount).ok_or(StakingError::MathOverflow)?;
+    msg!("{} tokens added to the reward reserve, now {}", amount, pool_config.reward_reserve);
+    pool_config.save(pool_config_acc)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn _process_staking_instruction(
+        program_id: &Pubkey, 
+        staking_acc: &AccountInfo,
+        owners_acc: &AccountInfo,
+        owner_token_acc: &AccountInfo,
+        pool_token_acc: &AccountInfo,
+        pool_config_acc: &AccountInfo,
+        token_amount: u64,
+        now: UnixTimestamp,
+        duration: u16) -> ProgramResult {
+    if !owners_acc.is_signer {
+        msg!("Holder {} must sign the stake", owners_acc.key);
+        return Err(StakingError::HolderNotSigner.into());
+    }
+    for (key, is_writable) in [
+        (staking_acc.key, staking_acc.is_writable),
+        (owner_token_acc.key, owner_token_acc.is_writable),
+        (pool_token_acc.key, pool_token_acc.is_writable),
+    ] {
+        if !is_writable {
+            msg!("Account {} must be writable", key);
+            return Err(StakingError::AccountNotWritable.into());
+        }
+    }
+    if staking_acc.owner != program_id {
+        msg!("Staking account does not have the correct program id");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_STAKING)?;
+    pool_config.check_vault(program_id, pool_token_acc)?;
+    check_holder_token_account(&pool_config, owners_acc, owner_token_acc, pool_token_acc)?;
+
+    let tier = pool_config.find_tier(duration).ok_or_else(|| {
+        msg!("Selected duration {} is not allowed", duration);
+        StakingError::TierNotFound
+    })?;
+    if tier.retired {
+        msg!("Tier {} days is closed for new stakes", duration);
+        return Err(StakingError::TierRetired.into());
+    }
+    let mut staking_info = StakingAccount::try_from_slice(&staking_acc.data.borrow())?;
+    if staking_info.closed {
+        return Err(StakingError::PositionClosed.into());
+    }
+    if staking_info.initialized { 
+        return Err(StakingError::StakingAccountAlreadyInitialized.into());
+    }
+
+    staking_info.initialized = true;
+    staking_info.holder = *owners_acc.key;
+    staking_info.created = now;
+    staking_info.lock_start = now;
+    staking_info.token_amount = token_amount;
+    staking_info.last_withdraw_date = now;
+    staking_info.snapshot_tier(tier);
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+    pool_config.add_principal(token_amount)?;
+    pool_config.update_interest_liability(&mut staking_info)?;
+    pool_config.check_interest_covered()?;
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    pool_config.save(pool_config_acc)
+}
+
+/// Adds tokens to an open position. Interest accrued so far is carried in extra_not_withdrawn_tokens,
+/// the lock starts over from now. The position keeps the tier terms it was opened with, later changes
+/// of the tier apply only to new positions and renewals.
+#[allow(clippy::too_many_arguments)]
+pub fn _process_add_stake_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    owner_token_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    token_amount: u64,
+    now: UnixTimestamp,
+) -> ProgramResult {
+    for (key, is_writable) in [
+        (staking_acc.key, staking_acc.is_writable),
+        (owner_token_acc.key, owner_token_acc.is_writable),
+        (pool_token_acc.key, pool_token_acc.is_writable),
+    ] {
+        if !is_writable {
+            msg!("Account {} must be writable", key);
+            return Err(StakingError::AccountNotWritable.into());
+        }
+    }
+    let (mut pool_config, mut staking_info) = load_holders_position(program_id, staking_acc, owners_acc, pool_token_acc, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_STAKING)?;
+    check_holder_token_account(&pool_config, owners_acc, owner_token_acc, pool_token_acc)?;
+    match pool_config.find_tier(staking_info.duration) {
+        Some(tier) if !tier.retired => {},
+        _ => {
+            msg!("Tier {} days is closed for new stakes", staking_info.duration);
+            return Err(StakingError::TierRetired.into());
+        }
+    }
+
+    // Снимок тира не трогаем: и набежавшее, и новый лок идут по условиям открытия позиции.
+    let accumulated_interest = staking_info.accrue_interest(now)?;
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+    staking_info.extra_not_withdrawn_tokens = staking_info.extra_not_withdrawn_tokens.checked_add(accumulated_interest).ok_or(StakingError::MathOverflow)?;
+    staking_info.token_amount = staking_info.token_amount.checked_add(token_amount).ok_or(StakingError::MathOverflow)?;
+    staking_info.lock_start = now;
+    msg!("Added {} tokens, {} tokens of interest carried over", token_amount, accumulated_interest);
+    pool_config.add_principal(token_amount)?;
+    pool_config.update_interest_liability(&mut staking_info)?;
+    pool_config.check_interest_covered()?;
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    pool_config.save(pool_config_acc)
+}
+
+// Open position of this program.
+fn load_position(program_id: &Pubkey, staking_acc: &AccountInfo) -> Result<StakingAccount, ProgramError> {
+    if staking_acc.owner != program_id {
+        msg!("Staking account does not have the correct program id");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !staking_acc.is_writable {
+        msg!("Staking account must be writable");
+        return Err(StakingError::AccountNotWritable.into());
+    }
+    let staking_info = StakingAccount::try_from_slice(&staking_acc.data.borrow())?;
+    if staking_info.closed {
+        msg!("Staking account is closed");
+        return Err(StakingError::PositionClosed.into());
+    }
+    if !staking_info.initialized {
+        return Err(StakingError::StakingAccountNotInitialized.into());
+    }
+    Ok(staking_info)
+}
+
+// Common checks of instructions made by the holder of an existing position.
+fn load_holders_position(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+) -> Result<(PoolConfig, StakingAccount), ProgramError> {
+    let staking_info = load_position(program_id, staking_acc)?;
+    let pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_vault(program_id, pool_token_acc)?;
+    check_holder(&staking_info, owners_acc)?;
+    Ok((pool_config, staking_info))
+}
+
+fn check_holder(staking_info: &StakingAccount, owners_acc: &AccountInfo) -> ProgramResult {
+    if &staking_info.holder != owners_acc.key {
+        msg!("Staking account can not be withdrawn to someone else");
+        return Err(StakingError::WrongHolder.into());
+    }
+    if !owners_acc.is_signer {
+        msg!("Holder {} must sign the instruction", owners_acc.key);
+        return Err(StakingError::HolderNotSigner.into());
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn _process_withdraw_interest_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    _owner_token_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    now: UnixTimestamp,
+    withdraw_strategy: WithdrawStrategy,
+) -> Result<Payout, ProgramError> {
+    let (mut pool_config, mut staking_info) = load_holders_position(program_id, staking_acc, owners_acc, pool_token_acc, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_INTEREST)?;
+    if withdraw_strategy == WithdrawStrategy::Compound {
+        pool_config.check_compound_allowed()?;
+    }
+    // Проценты, перенесенные при пополнении позиции, выплачиваются вместе с новыми.
+    let accumulated_interest = staking_info.accrue_interest(now)?
+        .checked_add(staking_info.extra_not_withdrawn_tokens).ok_or(StakingError::MathOverflow)?;
+    staking_info.extra_not_withdrawn_tokens = 0;
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+
+    let payout = match withdraw_strategy {
+        WithdrawStrategy::InterestOnly => Payout { principal: 0, interest: accumulated_interest },
+        WithdrawStrategy::Compound => {
+            staking_info.token_amount = staking_info.token_amount.checked_add(accumulated_interest).ok_or(StakingError::MathOverflow)?;
+            pool_config.add_principal(accumulated_interest)?;
+            Payout { principal: 0, interest: 0 }
+        },
+        WithdrawStrategy::CloseAccount => {
+            // Позиция после EmergencyExit без тела закрывается сразу, как только долг по процентам выплачен.
+            if staking_info.token_amount > 0 && !staking_info.lock_expired(now) {
+                msg!("Staking account is locked for {} days, use EarlyUnstake to exit now", staking_info.duration);
+                return Err(StakingError::LockNotExpired.into());
+            }
+            pool_config.remove_principal(staking_info.token_amount)?;
+            let payout = Payout { principal: staking_info.token_amount, interest: accumulated_interest };
+            staking_info.token_amount = 0;
+            payout
+        },
+        WithdrawStrategy::Principal(amount) => {
+            if !staking_info.lock_expired(now) {
+                msg!("Staking account is locked for {} days", staking_info.duration);
+                return Err(StakingError::LockNotExpired.into());
+            }
+            if amount == 0 || amount > staking_info.token_amount {
+                msg!("Can not withdraw {} tokens from the stake of {}", amount, staking_info.token_amount);
+                return Err(StakingError::InsufficientPrincipal.into());
+            }
+            // Сначала проценты по старому телу, потом уменьшаем тело.
+            staking_info.token_amount -= amount;
+            pool_config.remove_principal(amount)?;
+            Payout { principal: amount, interest: accumulated_interest }
+        }
+    };
+    let mut payout = payout;
+    if withdraw_strategy == WithdrawStrategy::CloseAccount {
+        pool_config.release_interest_liability(&mut staking_info)?;
+    } else {
+        pool_config.update_interest_liability(&mut staking_info)?;
+    }
+    if withdraw_strategy == WithdrawStrategy::Compound {
+        pool_config.take_from_reward_reserve(accumulated_interest)?;
+    } else {
+        payout.interest = pool_config.pay_interest(&mut staking_info, accumulated_interest)?;
+    }
+    if withdraw_strategy == WithdrawStrategy::CloseAccount && !staking_info.owed_to_holder() {
+        StakingAccount::wipe(staking_acc)?;
+    } else {
+        staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    }
+    pool_config.save(pool_config_acc)?;
+    Ok(payout)
+}
+
+/// Wipes the position before its lock is over. Returns the payout to the holder and the penalty kept by the pool,
+/// after the lock is over it is the same as CloseAccount.
+pub fn _process_early_unstake_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    now: UnixTimestamp,
+) -> Result<(Payout, u64), ProgramError> {
+    let (mut pool_config, mut staking_info) = load_holders_position(program_id, staking_acc, owners_acc, pool_token_acc, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_INTEREST)?;
+    let mut interest = staking_info.accrue_interest(now)?
+        .checked_add(staking_info.extra_not_withdrawn_tokens).ok_or(StakingError::MathOverflow)?;
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+    let mut penalty = 0;
+    if !staking_info.lock_expired(now) {
+        penalty = staking_info.early_unstake_penalty();
+        if penalty > 0 {
+            pool_config.check_penalty_receiver()?;
+        }
+        if staking_info.early_unstake_forfeits_interest {
+            // Сгорают только проценты текущего лока, они просто остаются в пуле. Перенесенные
+            // из прошлых локов проценты и долг пула по ним принадлежат холдеру.
+            interest = staking_info.extra_not_withdrawn_tokens;
+        }
+        msg!("Early unstake: penalty {} tokens, interest paid {}", penalty, interest);
+    }
+    // Сгоревшие проценты освобождают резерв для других позиций.
+    pool_config.release_interest_liability(&mut staking_info)?;
+    pool_config.remove_principal(staking_info.token_amount)?;
+    if pool_config.treasury == Pubkey::default() && !pool_config.has_reward_vault() {
+        // Штраф остается в vault и идет на выплату процентов.
+        pool_config.reward_reserve = pool_config.reward_reserve.checked_add(penalty).ok_or(StakingError::MathOverflow)?;
+    }
+    let principal = staking_info.token_amount - penalty;
+    staking_info.token_amount = 0;
+    staking_info.extra_not_withdrawn_tokens = 0;
+    let interest = pool_config.pay_interest(&mut staking_info, interest)?;
+    if !staking_info.owed_to_holder() {
+        StakingAccount::wipe(staking_acc)?;
+    } else {
+        staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    }
+    pool_config.save(pool_config_acc)?;
+    Ok((Payout { principal, interest }, penalty))
+}
+
+/// Returns the principal to send from the vault. The position is wiped only when nothing is owed to it anymore.
+pub fn _process_emergency_exit_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    now: UnixTimestamp,
+) -> Result<u64, ProgramError> {
+    let (mut pool_config, mut staking_info) = load_holders_position(program_id, staking_acc, owners_acc, pool_token_acc, pool_config_acc)?;
+    if pool_config.paused & PAUSE_INTEREST == 0 {
+        msg!("Pool is not paused, use CloseAccount or EarlyUnstake");
+        return Err(StakingError::PoolNotPaused.into());
+    }
+    // Проценты не платятся, но и не сгорают: их можно будет снять после снятия паузы.
+    let accumulated_interest = staking_info.accrue_interest(now)?;
+    staking_info.extra_not_withdrawn_tokens = staking_info.extra_not_withdrawn_tokens
+        .checked_add(accumulated_interest).ok_or(StakingError::MathOverflow)?;
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+    let principal = staking_info.token_amount;
+    pool_config.remove_principal(principal)?;
+    staking_info.token_amount = 0;
+    pool_config.update_interest_liability(&mut staking_info)?;
+    msg!("Emergency exit: {} tokens of principal, {} tokens of interest owed", principal, staking_info.extra_not_withdrawn_tokens);
+    if staking_info.owed_to_holder() {
+        staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    } else {
+        StakingAccount::wipe(staking_acc)?;
+    }
+    pool_config.save(pool_config_acc)?;
+    Ok(principal)
+}
+
+pub fn _process_set_auto_renew_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    auto_renew: bool,
+    compound: bool,
+) -> ProgramResult {
+    let mut staking_info = load_position(program_id, staking_acc)?;
+    check_holder(&staking_info, owners_acc)?;
+    PoolConfig::load(program_id, pool_config_acc)?.check_not_paused(PAUSE_POSITIONS)?;
+    staking_info.auto_renew = auto_renew;
+    staking_info.auto_renew_compound = compound;
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+pub fn _process_transfer_position_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    new_holder_acc: Option<&AccountInfo>,
+    new_holder: &Pubkey,
+    now: UnixTimestamp,
+) -> ProgramResult {
+    let mut staking_info = load_position(program_id, staking_acc)?;
+    check_holder(&staking_info, owners_acc)?;
+    let mut pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_POSITIONS)?;
+    if new_holder == owners_acc.key || *new_holder == Pubkey::default() {
+        msg!("Can not transfer the position to {}", new_holder);
+        return Err(StakingError::InvalidNewHolder.into());
+    }
+    if let Some(new_holder_acc) = new_holder_acc {
+        if new_holder_acc.key != new_holder || !new_holder_acc.is_signer {
+            msg!("Transfer must be signed by the new holder {}", new_holder);
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+    }
+    // Проценты переходят новому холдеру, старый может снять их до передачи.
+    let accumulated_interest = staking_info.accrue_interest(now)?;
+    staking_info.extra_not_withdrawn_tokens = staking_info.extra_not_withdrawn_tokens
+        .checked_add(accumulated_interest).ok_or(StakingError::MathOverflow)?;
+    staking_info.holder = *new_holder;
+    // Новый холдер сам решает про автопродление и капитализацию.
+    staking_info.auto_renew = false;
+    staking_info.auto_renew_compound = false;
+    staking_info.delegated_compounding = false;
+    // Проценты после лока теперь тоже обещаны позиции.
+    pool_config.update_interest_liability(&mut staking_info)?;
+    msg!("Position is transferred from {} to {}", owners_acc.key, new_holder);
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    pool_config.save(pool_config_acc)
+}
+
+pub fn _process_set_delegated_compounding_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    enabled: bool,
+) -> ProgramResult {
+    let mut staking_info = load_position(program_id, staking_acc)?;
+    check_holder(&staking_info, owners_acc)?;
+    PoolConfig::load(program_id, pool_config_acc)?.check_not_paused(PAUSE_POSITIONS)?;
+    staking_info.delegated_compounding = enabled;
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// Compounds the position on behalf of the holder, returns the keeper tip to send from the pool.
+pub fn _process_crank_compound_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    pool_token_acc: &AccountInfo,
+    keeper_token_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    now: UnixTimestamp,
+) -> Result<u64, ProgramError> {
+    let mut staking_info = load_position(program_id, staking_acc)?;
+    if !staking_info.delegated_compounding {
+        msg!("Holder has not enabled delegated compounding");
+        return Err(StakingError::DelegatedCompoundingDisabled.into());
+    }
+    let mut pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_INTEREST)?;
+    pool_config.check_vault(program_id, pool_token_acc)?;
+    pool_config.check_compound_allowed()?;
+    if keeper_token_acc.key == pool_token_acc.key {
+        msg!("Keeper can not be paid into the pool vault");
+        return Err(StakingError::SelfTransfer.into());
+    }
+    let keeper_token = unpack_token_account(keeper_token_acc)?;
+    if keeper_token.mint != pool_config.stake_mint {
+        msg!("Keeper token account holds {} instead of {}", keeper_token.mint, pool_config.stake_mint);
+        return Err(StakingError::MintMismatch.into());
+    }
+    let accumulated_interest = staking_info.accrue_interest(now)?
+        .checked_add(staking_info.extra_not_withdrawn_tokens).ok_or(StakingError::MathOverflow)?;
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+    // Чаевые не больше MAX_KEEPER_TIP_BPS от процентов и влезают в u64.
+    let tip = (u128::from(accumulated_interest) * u128::from(pool_config.keeper_tip_bps) / u128::from(BPS_DENOMINATOR)) as u64;
+    staking_info.token_amount = staking_info.token_amount
+        .checked_add(accumulated_interest - tip).ok_or(StakingError::MathOverflow)?;
+    staking_info.extra_not_withdrawn_tokens = 0;
+    pool_config.add_principal(accumulated_interest - tip)?;
+    pool_config.update_interest_liability(&mut staking_info)?;
+    pool_config.take_from_reward_reserve(accumulated_interest)?;
+    msg!("Compounded {} tokens, keeper tip {}", accumulated_interest - tip, tip);
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    pool_config.save(pool_config_acc)?;
+    Ok(tip)
+}
+
+/// Returns rewards to pay from every slot of PoolConfig.reward_streams.
+pub fn _process_claim_rewards_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    now: UnixTimestamp,
+) -> Result<[u64; MAX_REWARD_STREAMS], ProgramError> {
+    let mut staking_info = load_position(program_id, staking_acc)?;
+    check_holder(&staking_info, owners_acc)?;
+    let pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_INTEREST)?;
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+    let mut rewards = [0; MAX_REWARD_STREAMS];
+    for (reward, position) in rewards.iter_mut().zip(staking_info.reward_streams.iter_mut()) {
+        *reward = position.unclaimed;
+        position.unclaimed = 0;
+    }
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    Ok(rewards)
+}
+
+/// Holder renews a matured position into the tier of `duration`, 0 keeps the current tier.
+pub fn _process_renew_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    owners_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    now: UnixTimestamp,
+    duration: u16,
+    compound: bool,
+) -> ProgramResult {
+    let mut staking_info = load_position(program_id, staking_acc)?;
+    check_holder(&staking_info, owners_acc)?;
+    let mut pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_STAKING | PAUSE_INTEREST)?;
+    let duration = if duration == 0 { staking_info.duration } else { duration };
+    renew_position(&mut pool_config, &mut staking_info, now, duration, compound)?;
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    pool_config.save(pool_config_acc)
+}
+
+/// Anyone can renew a matured position with auto_renew into the same tier.
+pub fn _process_crank_renew_instruction(
+    program_id: &Pubkey,
+    staking_acc: &AccountInfo,
+    pool_config_acc: &AccountInfo,
+    now: UnixTimestamp,
+) -> ProgramResult {
+    let mut staking_info = load_position(program_id, staking_acc)?;
+    if !staking_info.auto_renew {
+        msg!("Holder has not enabled auto renew");
+        return Err(StakingError::AutoRenewDisabled.into());
+    }
+    let mut pool_config = PoolConfig::load(program_id, pool_config_acc)?;
+    pool_config.check_not_paused(PAUSE_STAKING | PAUSE_INTEREST)?;
+    let duration = staking_info.duration;
+    let compound = staking_info.auto_renew_compound;
+    renew_position(&mut pool_config, &mut staking_info, now, duration, compound)?;
+    staking_info.serialize(&mut &mut staking_acc.data.borrow_mut()[..])?;
+    pool_config.save(pool_config_acc)
+}
+
+// Время между окончанием лока и продлением начисляется по старому снимку тира, включая политику после лока,
+// новый лок начинается с момента продления. Ставка, штрафы и политика после лока берутся из тира на момент продления.
+fn renew_position(
+    pool_config: &mut PoolConfig,
+    staking_info: &mut StakingAccount,
+    now: UnixTimestamp,
+    duration: u16,
+    compound: bool,
+) -> ProgramResult {
+    if !staking_info.lock_expired(now) {
+        msg!("Staking account is locked for {} days", staking_info.duration);
+        return Err(StakingError::LockNotExpired.into());
+    }
+    if compound {
+        pool_config.check_compound_allowed()?;
+    }
+    let tier = pool_config.find_tier(duration).cloned().ok_or_else(|| {
+        msg!("Selected duration {} is not allowed", duration);
+        StakingError::TierNotFound
+    })?;
+    if tier.retired {
+        msg!("Tier {} days is closed for new stakes", duration);
+        return Err(StakingError::TierRetired.into());
+    }
+    let accumulated_interest = staking_info.accrue_interest(now)?
+        .checked_add(staking_info.extra_not_withdrawn_tokens).ok_or(StakingError::MathOverflow)?;
+    staking_info.accrue_stream_rewards(&pool_config.reward_streams, now)?;
+    if compound {
+        pool_config.add_principal(accumulated_interest)?;
+        staking_info.token_amount = staking_info.token_amount.checked_add(accumulated_interest).ok_or(StakingError::MathOverflow)?;
+        staking_info.extra_not_withdrawn_tokens = 0;
+    } else {
+        staking_info.extra_not_withdrawn_tokens = accumulated_interest;
+    }
+    staking_info.lock_start = now.max(staking_info.lock_end());
+    staking_info.snapshot_tier(&tier);
+    pool_config.update_interest_liability(staking_info)?;
+    if compound {
+        pool_config.take_from_reward_reserve(accumulated_interest)?;
+    }
+    pool_config.check_interest_covered()?;
+    msg!("Renewed for {} days until {}", duration, staking_info.lock_end());
+    Ok(())
+}
+
+// CloseAccount, EarlyUnstake and EmergencyExit keep the position open while the pool owes interest to it.
+fn position_wiped(staking_acc: &AccountInfo) -> bool {
+    matches!(StakingAccount::try_from_slice(&staking_acc.data.borrow()), Ok(staking_info) if staking_info.closed)
+}
+
+/// Moves all lamports of the wiped staking account to the rent receiver, runtime deletes it after the transaction.
+pub fn _close_staking_account(staking_acc: &AccountInfo, rent_receiver_acc: &AccountInfo) -> ProgramResult {
+    if staking_acc.key == rent_receiver_acc.key {
+        msg!("Rent receiver can not be the staking account itself");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !rent_receiver_acc.is_writable {
+        msg!("Rent receiver {} must be writable", rent_receiver_acc.key);
+        return Err(StakingError::AccountNotWritable.into());
+    }
+    let lamports = staking_acc.lamports();
+    **rent_receiver_acc.lamports.borrow_mut() = rent_receiver_acc.lamports().checked_add(lamports).ok_or(StakingError::MathOverflow)?;
+    **staking_acc.lamports.borrow_mut() = 0;
+    Ok(())
+}
+
+// Declare and export the program's entrypoint.
+// Programs using this crate for CPI enable `no-entrypoint` to avoid a duplicate symbol.
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
+
+// Program entrypoint's implementation
+pub fn process_instruction(
+    program_id: &Pubkey, // Public key of the account the hello world program was loaded into
+    accounts: &[AccountInfo], // The account to say hello to
+    _instruction_data: &[u8], // Ignored, all helloworld instructions are hellos
+) -> ProgramResult {
+    if let Err(error) = _process_instruction(program_id, accounts, _instruction_data) {
+        // Человекочитаемое описание ошибки в логах транзакции.
+        error.print::<StakingError>();
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Creates the staking account at its PDA, so positions can't be orphaned or created by someone else.
+fn create_staking_account<'a>(
+    program_id: &Pubkey,
+    staking_account: &AccountInfo<'a>,
+    owner_account: &AccountInfo<'a>,
+    pool_config_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    position_index: u64,
+) -> ProgramResult {
+    let index_bytes = position_index.to_le_bytes();
+    let seeds = &[STAKING_ACCOUNT_SEED, pool_config_account.key.as_ref(), owner_account.key.as_ref(), &index_bytes];
+    let (expected_staking_address, bump) = Pubkey::find_program_address(seeds, program_id);
+    if staking_account.key != &expected_staking_address {
+        msg!("Wrong staking account address. Expected {} but got {}", expected_staking_address, staking_account.key);
+        return Err(StakingError::InvalidStakingAccountAddress.into());
+    }
+    // Аккаунт по этому адресу может создать только сама программа, повторную инициализацию
+    // отсечет проверка initialized.
+    if staking_account.owner == program_id {
+        return Ok(());
+    }
+    let signer_seeds: &[&[u8]] = &[seeds[0], seeds[1], seeds[2], seeds[3], &[bump]];
+    create_pda_account(program_id, staking_account, payer_account, system_program, STAKING_ACCOUNT_SIZE, signer_seeds)
+}
+
+// Creates a rent exempt account of the program at a PDA signed with `signer_seeds`.
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    size: usize,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    use solana_program::sysvar::Sysvar;
+    let required_lamports = Rent::get()?.minimum_balance(size);
+    if account.lamports() == 0 {
+        let ix = system_instruction::create_account(
+            payer_account.key,
+            account.key,
+            required_lamports,
+            size as u64,
+            program_id,
+        );
+        return invoke_signed(&ix, &[
+            payer_account.clone(),
+            account.clone(),
+            system_program.clone(),
+        ], &[signer_seeds]);
+    }
+    // Кто-то заранее перевел лампорты на адрес PDA, create_account в этом случае падает.
+    let missing_lamports = required_lamports.saturating_sub(account.lamports());
+    if missing_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer_account.key, account.key, missing_lamports),
+            &[payer_account.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(account.key, size as u64),
+        &[account.clone(), system_program.clone()],
+        &[signer_seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, program_id),
+        &[account.clone(), system_program.clone()],
+        &[signer_seeds],
+    )
+}
+
+// Transfer signed by the holder, the accounts must be checked by the caller.
+fn transfer_to_pool<'a>(
+    owner_account: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    if token_program.key != &SPL_TOKEN_PROGRAM_ID {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let ix = spl_token::instruction::transfer(
+        &SPL_TOKEN_PROGRAM_ID,
+        owner_token_account.key,
+        pool_token_account.key,
+        owner_account.key,
+        &[owner_account.key],
+        amount,
+    )?;
+    msg!("Signer info: {}", owner_account.key);
+    msg!("Owner info: {}", Pubkey::new(&owner_token_account.data.borrow()[32..64]));
+    invoke(&ix, &[
+        owner_token_account.clone(),
+        pool_token_account.clone(),
+        owner_account.clone(),
+        token_program.clone(),
+    ])
+}
+
+// Transfer signed by the pool manager, the only way tokens leave the pool.
+fn transfer_from_pool<'a>(
+    program_id: &Pubkey,
+    pool_config: &PoolConfig,
+    pool_manager_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    if token_program.key != &SPL_TOKEN_PROGRAM_ID {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let pool_owner = &pool_config.check_pool_authority(program_id, pool_manager_account)?;
+    let ix = spl_token::instruction::transfer(
+        &SPL_TOKEN_PROGRAM_ID,
+        pool_token_account.key,
+        destination_account.key,
+        pool_owner,
+        &[pool_owner],
+        amount,
+    )?;
+    msg!("Invoke signed. Pool owner={}. Sending {} from pool", pool_owner, amount);
+    invoke_signed(&ix, &[
+        pool_manager_account.clone(),
+        pool_token_account.clone(),
+        destination_account.clone(),
+        token_program.clone(),
+    ], &[&[POOL_ADDRESS_SEED, &[pool_config.authority_bump]]])
+}
+
+// Principal goes from the vault, interest from the reward vault when the pool has one,
+// the reward accounts are the last in the instruction.
+#[allow(clippy::too_many_arguments)]
+fn pay_out<'a>(
+    program_id: &Pubkey,
+    pool_config: &PoolConfig,
+    pool_manager_account: &AccountInfo<'a>,
+    pool_token_account: &AccountInfo<'a>,
+    owner_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<'_, AccountInfo<'a>>,
+    payout: Payout,
+) -> ProgramResult {
+    if !pool_config.has_reward_vault() {
+        return transfer_from_pool(
+            program_id,
+            pool_config,
+            pool_manager_account,
+            pool_token_account,
+            owner_token_account,
+            token_program,
+            payout.total()?);
+    }
+    let reward_vault_account = next_account_info(account_info_iter)?;
+    let owner_reward_token_account = next_account_info(account_info_iter)?;
+    pool_config.check_reward_vault(program_id, reward_vault_account)?;
+    if payout.principal > 0 {
+        transfer_from_pool(
+            program_id,
+            pool_config,
+            pool_manager_account,
+            pool_token_account,
+            owner_token_account,
+            token_program,
+            payout.principal)?;
+    }
+    if payout.interest > 0 {
+        transfer_from_pool(
+            program_id,
+            pool_config,
+            pool_manager_account,
+            reward_vault_account,
+            owner_reward_token_account,
+            token_program,
+            payout.interest)?;
+    }
+    Ok(())
+}
+
+fn _process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _instruction_data: &[u8],
+) -> ProgramResult {
+    use solana_program::sysvar::Sysvar;
+    let instruction = Instruction::unpack(_instruction_data)?;
+    let account_info_iter = &mut accounts.iter();
+    match instruction {
+        Instruction::Stake { duration, amount, position_index } => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let owner_token_account = next_account_info(account_info_iter)?;
+            let pool_token_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let payer_account = next_account_info(account_info_iter)?;
+            let system_program = next_account_info(account_info_iter)?;
+            if system_program.key != &SYSTEM_PROGRAM_ID {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            create_staking_account(
+                program_id,
+                staking_account,
+                owner_account,
+                pool_config_account,
+                payer_account,
+                system_program,
+                position_index)?;
+            _process_staking_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                owner_token_account,
+                pool_token_account,
+                pool_config_account,
+                amount,
+                Clock::get()?.unix_timestamp,
+                duration)?;
+            transfer_to_pool(owner_account, owner_token_account, pool_token_account, token_program, amount)?;
+        },
+        Instruction::AddStake { amount } => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let owner_token_account = next_account_info(account_info_iter)?;
+            let pool_token_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            _process_add_stake_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                owner_token_account,
+                pool_token_account,
+                pool_config_account,
+                amount,
+                Clock::get()?.unix_timestamp)?;
+            transfer_to_pool(owner_account, owner_token_account, pool_token_account, token_program, amount)?;
+        },
+        Instruction::WithdrawInterest | Instruction::Compound | Instruction::CloseAccount | Instruction::WithdrawPrincipal { .. } => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let owner_token_account = next_account_info(account_info_iter)?;
+            let pool_token_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            let pool_manager_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let payout = _process_withdraw_interest_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                owner_token_account,
+                pool_token_account,
+                pool_config_account,
+                Clock::get()?.unix_timestamp,
+                match instruction {
+                    Instruction::WithdrawInterest => WithdrawStrategy::InterestOnly,
+                    Instruction::Compound => WithdrawStrategy::Compound,
+                    Instruction::CloseAccount => WithdrawStrategy::CloseAccount,
+                    Instruction::WithdrawPrincipal { amount } => WithdrawStrategy::Principal(amount),
+                    _ => unreachable!(),
+                })?;
+            let rent_receiver_account = if instruction == Instruction::CloseAccount {
+                Some(next_account_info(account_info_iter)?)
+            } else {
+                None
+            };
+            let pool_config = PoolConfig::load(program_id, pool_config_account)?;
+            pay_out(
+                program_id,
+                &pool_config,
+                pool_manager_account,
+                pool_token_account,
+                owner_token_account,
+                token_program,
+                account_info_iter,
+                payout)?;
+            // Позиция с долгом по процентам или наградам остается открытой.
+            if let Some(rent_receiver_account) = rent_receiver_account.filter(|_| position_wiped(staking_account)) {
+                _close_staking_account(staking_account, rent_receiver_account)?;
+            }
+        },
+        Instruction::InitializePool { tiers } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            let stake_mint_account = next_account_info(account_info_iter)?;
+            let pool_token_account = next_account_info(account_info_iter)?;
+            let system_program = next_account_info(account_info_iter)?;
+            if system_program.key != &SYSTEM_PROGRAM_ID {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            let (expected_pool_config_address, bump) = find_pool_config_address(program_id);
+            if pool_config_account.key != &expected_pool_config_address {
+                msg!("Wrong pool config address. Expected {} but got {}", expected_pool_config_address, pool_config_account.key);
+                return Err(StakingError::InvalidPoolConfig.into());
+            }
+            let program_data_account = next_account_info(account_info_iter)?;
+            let (_pool_owner, authority_bump) = find_pool_authority_address(program_id);
+            // Уже созданный конфиг отсечет проверка initialized.
+            if pool_config_account.owner != program_id {
+                create_pda_account(program_id, pool_config_account, admin_account, system_program, POOL_CONFIG_SIZE, &[POOL_CONFIG_SEED, &[bump]])?;
+            }
+            _process_initialize_pool_instruction(
+                program_id,
+                pool_config_account,
+                admin_account,
+                program_data_account,
+                stake_mint_account,
+                pool_token_account,
+                bump,
+                authority_bump,
+                tiers,
+                match (next_account_info(account_info_iter), next_account_info(account_info_iter)) {
+                    (Ok(reward_mint_account), Ok(reward_vault_account)) => Some((reward_mint_account, reward_vault_account)),
+                    (Err(_), _) => None,
+                    (Ok(_), Err(error)) => return Err(error),
+                })?;
+        },
+        Instruction::AddTier { tier } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_add_tier_instruction(program_id, pool_config_account, admin_account, tier)?;
+        },
+        Instruction::UpdateTierRate { duration, interest_numerator, interest_denominator } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_update_tier_rate_instruction(
+                program_id,
+                pool_config_account,
+                admin_account,
+                duration,
+                interest_numerator,
+                interest_denominator)?;
+        },
+        Instruction::RetireTier { duration } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_retire_tier_instruction(program_id, pool_config_account, admin_account, duration)?;
+        },
+        Instruction::EarlyUnstake => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let owner_token_account = next_account_info(account_info_iter)?;
+            let pool_token_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            let pool_manager_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let rent_receiver_account = next_account_info(account_info_iter)?;
+            let treasury_account = next_account_info(account_info_iter)?;
+            let (payout, penalty) = _process_early_unstake_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                pool_token_account,
+                pool_config_account,
+                Clock::get()?.unix_timestamp)?;
+            let pool_config = PoolConfig::load(program_id, pool_config_account)?;
+            pay_out(
+                program_id,
+                &pool_config,
+                pool_manager_account,
+                pool_token_account,
+                owner_token_account,
+                token_program,
+                account_info_iter,
+                payout)?;
+            if penalty > 0 && pool_config.treasury != Pubkey::default() {
+                if treasury_account.key != &pool_config.treasury {
+                    msg!("Wrong treasury. Expected {} but got {}", pool_config.treasury, treasury_account.key);
+                    return Err(StakingError::InvalidTreasury.into());
+                }
+                transfer_from_pool(
+                    program_id,
+                    &pool_config,
+                    pool_manager_account,
+                    pool_token_account,
+                    treasury_account,
+                    token_program,
+                    penalty)?;
+            }
+            if position_wiped(staking_account) {
+                _close_staking_account(staking_account, rent_receiver_account)?;
+            }
+        },
+        Instruction::SetTierPenalty { duration, early_unstake_penalty_bps, early_unstake_forfeits_interest } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_set_tier_penalty_instruction(
+                program_id,
+                pool_config_account,
+                admin_account,
+                duration,
+                early_unstake_penalty_bps,
+                early_unstake_forfeits_interest)?;
+        },
+        Instruction::SetTierPostMaturity { duration, post_maturity_accrual, flexible_interest_numerator, flexible_interest_denominator } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_set_tier_post_maturity_instruction(
+                program_id,
+                pool_config_account,
+                admin_account,
+                duration,
+                post_maturity_accrual,
+                flexible_interest_numerator,
+                flexible_interest_denominator)?;
+        },
+        Instruction::TransferPosition { new_holder, new_holder_signs } => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let new_holder_account = if new_holder_signs { Some(next_account_info(account_info_iter)?) } else { None };
+            _process_transfer_position_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                pool_config_account,
+                new_holder_account,
+                &new_holder,
+                Clock::get()?.unix_timestamp)?;
+        },
+        Instruction::SetDelegatedCompounding { enabled } => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            _process_set_delegated_compounding_instruction(program_id, staking_account, owner_account, pool_config_account, enabled)?;
+        },
+        Instruction::SetKeeperTip { keeper_tip_bps } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_set_keeper_tip_instruction(program_id, pool_config_account, admin_account, keeper_tip_bps)?;
+        },
+        Instruction::CrankCompound => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let pool_token_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            let pool_manager_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let keeper_token_account = next_account_info(account_info_iter)?;
+            let tip = _process_crank_compound_instruction(
+                program_id,
+                staking_account,
+                pool_token_account,
+                keeper_token_account,
+                pool_config_account,
+                Clock::get()?.unix_timestamp)?;
+            if tip > 0 {
+                let pool_config = PoolConfig::load(program_id, pool_config_account)?;
+                transfer_from_pool(
+                    program_id,
+                    &pool_config,
+                    pool_manager_account,
+                    pool_token_account,
+                    keeper_token_account,
+                    token_program,
+                    tip)?;
+            }
+        },
+        Instruction::ClaimRewards => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            let pool_manager_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let rewards = _process_claim_rewards_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                pool_config_account,
+                Clock::get()?.unix_timestamp)?;
+            let pool_config = PoolConfig::load(program_id, pool_config_account)?;
+            for (stream, reward) in pool_config.reward_streams.iter().zip(rewards) {
+                if stream.id == 0 {
+                    continue;
+                }
+                let stream_vault_account = next_account_info(account_info_iter)?;
+                let owner_reward_token_account = next_account_info(account_info_iter)?;
+                if stream_vault_account.key != &stream.vault {
+                    msg!("Wrong vault of reward stream {}. Expected {} but got {}", stream.id, stream.vault, stream_vault_account.key);
+                    return Err(StakingError::InvalidPoolVault.into());
+                }
+                if reward > 0 {
+                    transfer_from_pool(
+                        program_id,
+                        &pool_config,
+                        pool_manager_account,
+                        stream_vault_account,
+                        owner_reward_token_account,
+                        token_program,
+                        reward)?;
+                }
+            }
+        },
+        Instruction::AddRewardStream { start, end, rates } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            let mint_account = next_account_info(account_info_iter)?;
+            let stream_vault_account = next_account_info(account_info_iter)?;
+            _process_add_reward_stream_instruction(
+                program_id,
+                pool_config_account,
+                admin_account,
+                mint_account,
+                stream_vault_account,
+                start,
+                end,
+                rates)?;
+        },
+        Instruction::RemoveRewardStream { id } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_remove_reward_stream_instruction(program_id, pool_config_account, admin_account, id, Clock::get()?.unix_timestamp)?;
+        },
+        Instruction::SetAutoRenew { auto_renew, compound } => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            _process_set_auto_renew_instruction(program_id, staking_account, owner_account, pool_config_account, auto_renew, compound)?;
+        },
+        Instruction::Renew { duration, compound } => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            _process_renew_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                pool_config_account,
+                Clock::get()?.unix_timestamp,
+                duration,
+                compound)?;
+        },
+        Instruction::CrankRenew => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            _process_crank_renew_instruction(program_id, staking_account, pool_config_account, Clock::get()?.unix_timestamp)?;
+        },
+        Instruction::SetInsolvencyPolicy { insolvency_policy } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_set_insolvency_policy_instruction(program_id, pool_config_account, admin_account, insolvency_policy)?;
+        },
+        Instruction::SetPause { paused } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            _process_set_pause_instruction(program_id, pool_config_account, admin_account, paused)?;
+        },
+        Instruction::EmergencyExit => {
+            let staking_account = next_account_info(account_info_iter)?;
+            let owner_account = next_account_info(account_info_iter)?;
+            let owner_token_account = next_account_info(account_info_iter)?;
+            let pool_token_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            let pool_manager_account = next_account_info(account_info_iter)?;
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let rent_receiver_account = next_account_info(account_info_iter)?;
+            let principal = _process_emergency_exit_instruction(
+                program_id,
+                staking_account,
+                owner_account,
+                pool_token_account,
+                pool_config_account,
+                Clock::get()?.unix_timestamp)?;
+            let pool_config = PoolConfig::load(program_id, pool_config_account)?;
+            if principal > 0 {
+                transfer_from_pool(
+                    program_id,
+                    &pool_config,
+                    pool_manager_account,
+                    pool_token_account,
+                    owner_token_account,
+                    token_program,
+                    principal)?;
+            }
+            if position_wiped(staking_account) {
+                _close_staking_account(staking_account, rent_receiver_account)?;
+            }
+        },
+        Instruction::FundRewards { amount } => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let funder_account = next_account_info(account_info_iter)?;
+            let funder_token_account = next_account_info(account_info_iter)?;
+            let interest_vault_account = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            _process_fund_rewards_instruction(
+                program_id,
+                pool_config_account,
+                funder_account,
+                funder_token_account,
+                interest_vault_account,
+                amount)?;
+            transfer_to_pool(funder_account, funder_token_account, interest_vault_account, token_program, amount)?;
+        },
+        Instruction::SetTreasury => {
+            let pool_config_account = next_account_info(account_info_iter)?;
+            let admin_account = next_account_info(account_info_iter)?;
+            let treasury_account = next_account_info(account_info_iter)?;
+            _process_set_treasury_instruction(program_id, pool_config_account, admin_account, treasury_account)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Sanity tests
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_cast)]
+mod test {
+    use super::*;
+    use solana_program::{clock::Epoch, program_option::COption};
+    use spl_token::state::AccountState;
+
+    const TEST_MINT: Pubkey = Pubkey::new_from_array([1; 32]);
+    const TEST_VAULT: Pubkey = Pubkey::new_from_array([2; 32]);
+    const TEST_POOL_TOTALS: u64 = 1_000_000_000_000;
+
+    fn token_account_data(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Vec<u8> {
+        let account = TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            delegate: COption::None,
+            state: AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        };
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount::pack(account, &mut data).unwrap();
+        data
+    }
+
+    fn mint_data() -> Vec<u8> {
+        let mint = Mint {
+            mint_authority: COption::None,
+            supply: 0,
+            decimals: 9,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        let mut data = vec![0; Mint::LEN];
+        Mint::pack(mint, &mut data).unwrap();
+        data
+    }
+
+    fn default_tiers() -> Vec<Tier> {
+        vec![
+            Tier { duration: 180, interest_numerator: 15, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: true, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 },
+            Tier { duration: 360, interest_numerator: 17, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: true, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 },
+        ]
+    }
+
+    fn pool_config_data(program_id: &Pubkey, admin: &Pubkey) -> (Pubkey, Vec<u8>) {
+        let (pool_config_key, bump) = Pubkey::find_program_address(&[POOL_CONFIG_SEED], program_id);
+        let (_pool_owner, authority_bump) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], program_id);
+        let pool_config = PoolConfig {
+            initialized: true,
+            admin: *admin,
+            stake_mint: TEST_MINT,
+            vault: TEST_VAULT,
+            bump,
+            authority_bump,
+            treasury: Pubkey::default(),
+            keeper_tip_bps: 0,
+            reward_mint: Pubkey::default(),
+            reward_vault: Pubkey::default(),
+            // Позиции в тестах часто создаются прямо в данных аккаунта, поэтому счетчики с запасом.
+            total_principal: TEST_POOL_TOTALS,
+            reward_reserve: TEST_POOL_TOTALS,
+            interest_liability: 0,
+            insolvency_policy: InsolvencyPolicy::Revert,
+            paused: 0,
+            last_reward_stream_id: 0,
+            reward_streams: [RewardStream::default(); MAX_REWARD_STREAMS],
+            tiers: default_tiers(),
+        };
+        let mut data = vec![0; POOL_CONFIG_SIZE];
+        pool_config.serialize(&mut &mut data[..]).unwrap();
+        (pool_config_key, data)
+    }
+
+    #[test]
+    fn test_initialize_staking_account() {
+        // Проверка инициализации staking-PDA аккаунта.
+        // Нужные значения сохраняются + не допускается повторная инициализация.
+        let program_id = Pubkey::new_unique();
+    
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 1_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let pools_token_account_key = TEST_VAULT;
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &pools_token_account_key,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        assert_eq!(
+            StakingAccount::try_from_slice(&staking_account.data.borrow())
+                .unwrap()
+                .initialized,
+            false
+        );
+        
+        _process_staking_instruction(
+            &program_id, 
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            12u64,
+            1234567890 as UnixTimestamp,
+            360u16).unwrap();
+
+        let staking_account_initialized = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_account_initialized.initialized, true);
+        assert_eq!(staking_account_initialized.holder, owner);
+        assert_eq!(staking_account_initialized.created, 1234567890 as UnixTimestamp);
+        assert_eq!(staking_account_initialized.token_amount, 12u64);
+        assert_eq!(staking_account_initialized.extra_not_withdrawn_tokens, 0u64);
+        assert_eq!(staking_account_initialized.last_withdraw_date, 1234567890 as UnixTimestamp);
+        assert_eq!(staking_account_initialized.interest_numerator, 17u64);
+        assert_eq!(staking_account_initialized.interest_denominator, 100u64);
+
+        let second_invocation_result = _process_staking_instruction(
+            &program_id, 
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            12u64,
+            1234567890 as UnixTimestamp,
+            360u16);
+        assert_eq!(second_invocation_result, Err(StakingError::StakingAccountAlreadyInitialized.into()));
+    }
+
+    #[test]
+    fn test_wrong_owner() {
+        // Это довольно странный тест, но все так делают почему-то...
+        // Мне страшно идти на поводу своих принципов и не делать его,
+        // даже если я не вижу в этом смысла.
+        let program_id = Pubkey::new_unique();
+        let other_program_id = Pubkey::new_unique();
+    
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &other_program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 1_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let pools_token_account_key = TEST_VAULT;
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &pools_token_account_key,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let result = _process_staking_instruction(
+            &program_id, 
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            12u64,
+            1234567890 as UnixTimestamp,
+            360u16);
+        assert_eq!(result.is_ok(), false);
+    }
+
+    #[test]
+    fn test_wrong_pool_id() {
+        // Застейкать в собственный токен-аккаунт вместо хранилища пула нельзя.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 12);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let result = _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &owners_token_account,
+            &pool_config_account,
+            12u64,
+            1234567890 as UnixTimestamp,
+            360u16);
+        assert_eq!(result, Err(StakingError::InvalidPoolVault.into()));
+
+        // Хранилище по правильному адресу, но принадлежит не пулу или хранит другой токен.
+        let pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+        pools_token_account.data.borrow_mut().copy_from_slice(&token_account_data(&TEST_MINT, &owner, 0));
+        assert_eq!(
+            pool_config.check_vault(&program_id, &pools_token_account),
+            Err(StakingError::VaultNotOwnedByPool.into())
+        );
+        pools_token_account.data.borrow_mut().copy_from_slice(&token_account_data(&Pubkey::new_unique(), &pool_owner, 0));
+        assert_eq!(
+            pool_config.check_vault(&program_id, &pools_token_account),
+            Err(StakingError::MintMismatch.into())
+        );
+        pools_token_account.data.borrow_mut().copy_from_slice(&token_account_data(&TEST_MINT, &pool_owner, 0));
+        pool_config.check_vault(&program_id, &pools_token_account).unwrap();
+        assert_eq!(staking_account.data.borrow()[0], 0);
+    }
+
+    #[test]
+    fn test_stake_account_validation() {
+        // Холдер должен подписать стейк и переводить со своего счета в токене пула.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 12);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let stake = |staking: &AccountInfo, owner: &AccountInfo, owner_token: &AccountInfo| {
+            _process_staking_instruction(
+                &program_id,
+                staking,
+                owner,
+                owner_token,
+                &pools_token_account,
+                &pool_config_account,
+                12u64,
+                1234567890 as UnixTimestamp,
+                360u16)
+        };
+
+        let mut unsigned_owner = owners_account.clone();
+        unsigned_owner.is_signer = false;
+        assert_eq!(
+            stake(&staking_account, &unsigned_owner, &owners_token_account),
+            Err(StakingError::HolderNotSigner.into())
+        );
+
+        let mut readonly_staking_account = staking_account.clone();
+        readonly_staking_account.is_writable = false;
+        assert_eq!(
+            stake(&readonly_staking_account, &owners_account, &owners_token_account),
+            Err(StakingError::AccountNotWritable.into())
+        );
+
+        let mut readonly_owner_token_account = owners_token_account.clone();
+        readonly_owner_token_account.is_writable = false;
+        assert_eq!(
+            stake(&staking_account, &owners_account, &readonly_owner_token_account),
+            Err(StakingError::AccountNotWritable.into())
+        );
+
+        assert_eq!(
+            stake(&staking_account, &owners_account, &pools_token_account),
+            Err(StakingError::SelfTransfer.into())
+        );
+
+        let mut foreign_owner_token_account = owners_token_account.clone();
+        foreign_owner_token_account.owner = &program_id;
+        assert_eq!(
+            stake(&staking_account, &owners_account, &foreign_owner_token_account),
+            Err(ProgramError::IncorrectProgramId)
+        );
+
+        owners_token_account.data.borrow_mut().copy_from_slice(&token_account_data(&TEST_MINT, &Pubkey::new_unique(), 12));
+        assert_eq!(
+            stake(&staking_account, &owners_account, &owners_token_account),
+            Err(StakingError::TokenAccountNotOwnedByHolder.into())
+        );
+
+        owners_token_account.data.borrow_mut().copy_from_slice(&token_account_data(&Pubkey::new_unique(), &owner, 12));
+        assert_eq!(
+            stake(&staking_account, &owners_account, &owners_token_account),
+            Err(StakingError::MintMismatch.into())
+        );
+
+        owners_token_account.data.borrow_mut().copy_from_slice(&token_account_data(&TEST_MINT, &owner, 12));
+        assert_eq!(staking_account.data.borrow()[0], 0);
+        stake(&staking_account, &owners_account, &owners_token_account).unwrap();
+        assert_eq!(
+            StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap().holder,
+            owner
+        );
+    }
+
+    #[test]
+    fn test_wrong_duration() {
+        let program_id = Pubkey::new_unique();    
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 1_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let pools_token_account_key = TEST_VAULT;
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &pools_token_account_key,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let result = _process_staking_instruction(
+            &program_id, 
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            12u64,
+            1234567890 as UnixTimestamp,
+            45u16);
+        assert_eq!(result, Err(StakingError::TierNotFound.into()));
+    }
+
+    #[test]
+    fn test_initialize_pool() {
+        let program_id = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let stake_mint = Pubkey::new_unique();
+        let pool_token = Pubkey::new_unique();
+        let (pool_config_key, bump) = Pubkey::find_program_address(&[POOL_CONFIG_SEED], &program_id);
+        let (pool_owner, authority_bump) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+
+        let mut pool_config_lamports = 0;
+        let mut pool_config_data = vec![0; POOL_CONFIG_SIZE];
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let mut admin_lamports = 0;
+        let mut admin_data = vec![0; 0];
+        let admin_account = AccountInfo::new(
+            &admin,
+            true,
+            true,
+            &mut admin_lamports,
+            &mut admin_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let mut stake_mint_lamports = 0;
+        let mut stake_mint_data = mint_data();
+        let stake_mint_account = AccountInfo::new(
+            &stake_mint,
+            false,
+            false,
+            &mut stake_mint_lamports,
+            &mut stake_mint_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let mut pool_token_lamports = 0;
+        let mut pool_token_data = token_account_data(&stake_mint, &pool_owner, 0);
+        let pool_token_account = AccountInfo::new(
+            &pool_token,
+            false,
+            false,
+            &mut pool_token_lamports,
+            &mut pool_token_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let upgradeable_loader = bpf_loader_upgradeable::id();
+        let (program_data, _) = Pubkey::find_program_address(&[program_id.as_ref()], &upgradeable_loader);
+        let mut program_data_lamports = 0;
+        let mut program_data_data = vec![0; PROGRAM_DATA_HEADER_SIZE];
+        program_data_data[..4].copy_from_slice(&3u32.to_le_bytes());
+        program_data_data[12] = 1;
+        program_data_data[13..].copy_from_slice(admin.as_ref());
+        let program_data_account = AccountInfo::new(
+            &program_data,
+            false,
+            false,
+            &mut program_data_lamports,
+            &mut program_data_data,
+            &upgradeable_loader,
+            false,
+            Epoch::default(),
+        );
+
+        // Чужой ключ не может стать админом пула.
+        let stranger = Pubkey::new_unique();
+        let mut stranger_lamports = 0;
+        let mut stranger_data = vec![0; 0];
+        let stranger_account = AccountInfo::new(
+            &stranger,
+            true,
+            true,
+            &mut stranger_lamports,
+            &mut stranger_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+        assert_eq!(
+            _process_initialize_pool_instruction(
+                &program_id,
+                &pool_config_account,
+                &stranger_account,
+                &program_data_account,
+                &stake_mint_account,
+                &pool_token_account,
+                bump,
+                authority_bump,
+                default_tiers(),
+                None),
+            Err(StakingError::NotUpgradeAuthority.into())
+        );
+        // Программа без upgrade authority больше не дает создать пул.
+        program_data_account.data.borrow_mut()[12] = 0;
+        assert_eq!(
+            _process_initialize_pool_instruction(
+                &program_id,
+                &pool_config_account,
+                &admin_account,
+                &program_data_account,
+                &stake_mint_account,
+                &pool_token_account,
+                bump,
+                authority_bump,
+                default_tiers(),
+                None),
+            Err(StakingError::NotUpgradeAuthority.into())
+        );
+        program_data_account.data.borrow_mut()[12] = 1;
+
+        // Повторяющиеся сроки недопустимы.
+        let mut duplicated_tiers = default_tiers();
+        duplicated_tiers.push(duplicated_tiers[0].clone());
+        assert_eq!(
+            _process_initialize_pool_instruction(
+                &program_id,
+                &pool_config_account,
+                &admin_account,
+                &program_data_account,
+                &stake_mint_account,
+                &pool_token_account,
+                bump,
+                authority_bump,
+                duplicated_tiers,
+                None),
+            Err(StakingError::TierAlreadyExists.into())
+        );
+
+        // Проценты не могут платиться из того же vault, что хранит тело.
+        assert_eq!(
+            _process_initialize_pool_instruction(
+                &program_id,
+                &pool_config_account,
+                &admin_account,
+                &program_data_account,
+                &stake_mint_account,
+                &pool_token_account,
+                bump,
+                authority_bump,
+                default_tiers(),
+                Some((&stake_mint_account, &pool_token_account))),
+            Err(StakingError::SelfTransfer.into())
+        );
+
+        // Штраф в пуле с reward vault некуда деть, пока нет казны.
+        let reward_vault = Pubkey::new_unique();
+        let mut reward_vault_lamports = 0;
+        let mut reward_vault_data = token_account_data(&stake_mint, &pool_owner, 0);
+        let reward_vault_account = AccountInfo::new(
+            &reward_vault,
+            false,
+            false,
+            &mut reward_vault_lamports,
+            &mut reward_vault_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+        let mut penalty_tiers = default_tiers();
+        penalty_tiers[1].early_unstake_penalty_bps = 500;
+        assert_eq!(
+            _process_initialize_pool_instruction(
+                &program_id,
+                &pool_config_account,
+                &admin_account,
+                &program_data_account,
+                &stake_mint_account,
+                &pool_token_account,
+                bump,
+                authority_bump,
+                penalty_tiers,
+                Some((&stake_mint_account, &reward_vault_account))),
+            Err(StakingError::TreasuryRequired.into())
+        );
+
+        _process_initialize_pool_instruction(
+            &program_id,
+            &pool_config_account,
+            &admin_account,
+            &program_data_account,
+            &stake_mint_account,
+            &pool_token_account,
+            bump,
+            authority_bump,
+            default_tiers(),
+            None).unwrap();
+        assert!(!PoolConfig::load(&program_id, &pool_config_account).unwrap().has_reward_vault());
+
+        let pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+        assert_eq!(pool_config.admin, admin);
+        assert_eq!(pool_config.stake_mint, stake_mint);
+        assert_eq!(pool_config.vault, pool_token);
+        assert_eq!(pool_config.authority_bump, authority_bump);
+        assert_eq!(pool_config.tiers, default_tiers());
+        assert_eq!(pool_config.find_tier(360).unwrap().interest_numerator, 17);
+        assert_eq!(pool_config.find_tier(45), None);
+
+        let second_invocation_result = _process_initialize_pool_instruction(
+            &program_id,
+            &pool_config_account,
+            &admin_account,
+            &program_data_account,
+            &stake_mint_account,
+            &pool_token_account,
+            bump,
+            authority_bump,
+            default_tiers(),
+            None);
+        assert_eq!(second_invocation_result, Err(StakingError::PoolAlreadyInitialized.into()));
+    }
+
+    #[test]
+    fn test_forged_pool_config() {
+        // Конфиг, созданный не по PDA адресу, принимать нельзя, иначе любой может назначить себе проценты.
+        let program_id = Pubkey::new_unique();
+        let (_pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let forged_pool_config_key = Pubkey::new_unique();
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &forged_pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+        assert_eq!(
+            PoolConfig::load(&program_id, &pool_config_account).unwrap_err(),
+            StakingError::InvalidPoolConfig.into()
+        );
+    }
+
+    #[test]
+    fn test_wrong_pool_manager() {
+        // Bump берется из конфига, подсунуть другой аккаунт вместо менеджера пула нельзя.
+        let program_id = Pubkey::new_unique();
+        let (_pool_config_key, pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let pool_config = PoolConfig::deserialize(&mut &pool_config_data[..]).unwrap();
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+
+        let forged_pool_manager_key = Pubkey::new_unique();
+        let mut pool_manager_lamports = 0;
+        let mut pool_manager_data = vec![0; 0];
+        let mut pool_manager_account = AccountInfo::new(
+            &forged_pool_manager_key,
+            false,
+            false,
+            &mut pool_manager_lamports,
+            &mut pool_manager_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+        assert_eq!(
+            pool_config.check_pool_authority(&program_id, &pool_manager_account),
+            Err(StakingError::InvalidPoolAuthority.into())
+        );
+
+        pool_manager_account.key = &pool_owner;
+        assert_eq!(pool_config.check_pool_authority(&program_id, &pool_manager_account), Ok(pool_owner));
+    }
+
+    #[test]
+    fn test_interest_rate_is_locked_at_stake_time() {
+        // Изменение ставки тира не должно задним числом менять проценты по уже открытым позициям.
+        let program_id = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 1_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let pools_token_account_key = TEST_VAULT;
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &pools_token_account_key,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let mut admin_lamports = 0;
+        let mut admin_data = vec![0; 0];
+        let admin_account = AccountInfo::new(
+            &admin,
+            true,
+            false,
+            &mut admin_lamports,
+            &mut admin_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &admin);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            1234567890 as UnixTimestamp,
+            360u16).unwrap();
+
+        _process_update_tier_rate_instruction(&program_id, &pool_config_account, &admin_account, 360, 50, 100).unwrap();
+
+        let interest = _process_withdraw_interest_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1234567890 + SECONDS_PER_YEAR as UnixTimestamp,
+            WithdrawStrategy::InterestOnly).unwrap().interest;
+        assert_eq!(interest, 170_000u64);
+    }
+
+    #[test]
+    fn test_early_unstake() {
+        // Досрочный выход: штраф с тела и, если так настроен тир, сгорание процентов.
+        let program_id = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let short_staking_account_key = Pubkey::new_unique();
+        let mut short_staking_account_lamports = 0;
+        let mut short_staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let short_staking_account = AccountInfo::new(
+            &short_staking_account_key,
+            false,
+            true,
+            &mut short_staking_account_lamports,
+            &mut short_staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let long_staking_account_key = Pubkey::new_unique();
+        let mut long_staking_account_lamports = 0;
+        let mut long_staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let long_staking_account = AccountInfo::new(
+            &long_staking_account_key,
+            false,
+            true,
+            &mut long_staking_account_lamports,
+            &mut long_staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let mut admin_lamports = 0;
+        let mut admin_data = vec![0; 0];
+        let admin_account = AccountInfo::new(
+            &admin,
+            true,
+            false,
+            &mut admin_lamports,
+            &mut admin_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let treasury_key = Pubkey::new_unique();
+        let mut treasury_lamports = 0;
+        let mut treasury_data = token_account_data(&Pubkey::new_unique(), &admin, 0);
+        let treasury_account = AccountInfo::new(
+            &treasury_key,
+            false,
+            false,
+            &mut treasury_lamports,
+            &mut treasury_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &admin);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        assert_eq!(
+            _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &admin_account, 180, 10_001, false),
+            Err(StakingError::InvalidTier.into())
+        );
+        assert_eq!(
+            _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &admin_account, 90, 1_000, false),
+            Err(StakingError::TierNotFound.into())
+        );
+        _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &admin_account, 180, 1_000, false).unwrap();
+        _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &admin_account, 360, 500, true).unwrap();
+        assert_eq!(
+            _process_set_tier_post_maturity_instruction(&program_id, &pool_config_account, &admin_account, 180, PostMaturityAccrual::Flexible, 3, 0),
+            Err(StakingError::InvalidTier.into())
+        );
+        _process_set_tier_post_maturity_instruction(&program_id, &pool_config_account, &admin_account, 180, PostMaturityAccrual::Stop, 0, 0).unwrap();
+
+        assert_eq!(
+            _process_set_treasury_instruction(&program_id, &pool_config_account, &admin_account, &treasury_account),
+            Err(StakingError::MintMismatch.into())
+        );
+        assert_eq!(
+            _process_set_treasury_instruction(&program_id, &pool_config_account, &admin_account, &pools_token_account),
+            Err(StakingError::SelfTransfer.into())
+        );
+        treasury_account.data.borrow_mut().copy_from_slice(&token_account_data(&TEST_MINT, &admin, 0));
+        _process_set_treasury_instruction(&program_id, &pool_config_account, &admin_account, &treasury_account).unwrap();
+        assert_eq!(PoolConfig::load(&program_id, &pool_config_account).unwrap().treasury, treasury_key);
+
+        let created = 1234567890 as UnixTimestamp;
+        for (staking_account, duration) in [(&short_staking_account, 180u16), (&long_staking_account, 360u16)] {
+            _process_staking_instruction(
+                &program_id,
+                staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                1_000_000u64,
+                created,
+                duration).unwrap();
+        }
+        // Штраф зафиксирован на момент стейка.
+        _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &admin_account, 180, 5_000, true).unwrap();
+
+        let early_unstake = |staking_account: &AccountInfo, now: UnixTimestamp| {
+            _process_early_unstake_instruction(
+                &program_id,
+                staking_account,
+                &owners_account,
+                &pools_token_account,
+                &pool_config_account,
+                now).and_then(|(payout, penalty)| Ok((payout.total()?, penalty)))
+        };
+        // 90 дней под 15% = 37500 процентов, штраф 10% тела.
+        assert_eq!(early_unstake(&short_staking_account, created + 90 * 24 * 3600), Ok((937_500u64, 100_000u64)));
+        assert_eq!(
+            StakingAccount::try_from_slice(&short_staking_account.data.borrow()).unwrap().closed,
+            true
+        );
+        assert_eq!(
+            early_unstake(&short_staking_account, created + 90 * 24 * 3600),
+            Err(StakingError::PositionClosed.into())
+        );
+        // 90 дней под 17% переносятся пополнением и уже не сгорают.
+        _process_add_stake_instruction(
+            &program_id,
+            &long_staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created + 90 * 24 * 3600).unwrap();
+        assert_eq!(
+            StakingAccount::try_from_slice(&long_staking_account.data.borrow()).unwrap().extra_not_withdrawn_tokens,
+            42_500u64
+        );
+        // Проценты за 90 дней нового лока сгорают, штраф 5% тела, перенесенные выплачиваются.
+        assert_eq!(early_unstake(&long_staking_account, created + 180 * 24 * 3600), Ok((1_942_500u64, 100_000u64)));
+    }
+
+    #[test]
+    fn test_withdraw_principal() {
+        // После окончания лока можно забрать часть тела, остаток продолжает приносить проценты.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        let withdraw = |now: UnixTimestamp, withdraw_strategy: WithdrawStrategy| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                withdraw_strategy).and_then(|payout| payout.total())
+        };
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            180u16).unwrap();
+
+        assert_eq!(
+            withdraw(created + 179 * day, WithdrawStrategy::Principal(1)),
+            Err(StakingError::LockNotExpired.into())
+        );
+        assert_eq!(
+            withdraw(created + 180 * day, WithdrawStrategy::Principal(1_000_001)),
+            Err(StakingError::InsufficientPrincipal.into())
+        );
+        assert_eq!(
+            withdraw(created + 180 * day, WithdrawStrategy::Principal(0)),
+            Err(StakingError::InsufficientPrincipal.into())
+        );
+        // 75000 процентов за 180 дней под 15% плюс 400000 тела.
+        assert_eq!(withdraw(created + 180 * day, WithdrawStrategy::Principal(400_000)), Ok(475_000u64));
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.token_amount, 600_000u64);
+        assert_eq!(staking_info.last_withdraw_date, created + 180 * day);
+
+        // Дальше проценты идут уже на оставшиеся 600000.
+        assert_eq!(withdraw(created + 360 * day, WithdrawStrategy::InterestOnly), Ok(45_000u64));
+        assert_eq!(withdraw(created + 360 * day, WithdrawStrategy::Principal(600_000)), Ok(600_000u64));
+        assert_eq!(withdraw(created + 360 * day, WithdrawStrategy::CloseAccount), Ok(0u64));
+    }
+
+    #[test]
+    fn test_renew() {
+        // Проценты, набежавшие до пополнения, не теряются и выплачиваются любой стратегией.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            180u16).unwrap();
+        let renew = |now: UnixTimestamp, duration: u16, compound: bool| {
+            _process_renew_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, now, duration, compound)
+        };
+        let crank = |now: UnixTimestamp| {
+            _process_crank_renew_instruction(&program_id, &staking_account, &pool_config_account, now)
+        };
+
+        assert_eq!(renew(created + 179 * day, 0, false), Err(StakingError::LockNotExpired.into()));
+        assert_eq!(renew(created + 200 * day, 90, false), Err(StakingError::TierNotFound.into()));
+        assert_eq!(crank(created + 200 * day), Err(StakingError::AutoRenewDisabled.into()));
+
+        // 200 дней под 15%, новый лок на 360 дней отсчитывается от продления.
+        renew(created + 200 * day, 360, false).unwrap();
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.token_amount, 1_000_000u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 83_333u64);
+        assert_eq!(staking_info.lock_start, created + 200 * day);
+        assert_eq!(staking_info.duration, 360);
+        assert_eq!(staking_info.interest_numerator, 17);
+        assert_eq!(crank(created + 539 * day), Err(StakingError::AutoRenewDisabled.into()));
+
+        _process_set_auto_renew_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, true, true).unwrap();
+        assert_eq!(crank(created + 559 * day), Err(StakingError::LockNotExpired.into()));
+        // 83333 перенесенных + 360 дней под 17% добавляются к телу.
+        crank(created + 560 * day).unwrap();
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.token_amount, 1_253_333u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 0u64);
+        assert_eq!(staking_info.lock_start, created + 560 * day);
+        assert_eq!(staking_info.created, created);
+
+        assert_eq!(
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                created + 940 * day,
+                WithdrawStrategy::CloseAccount),
+            Ok(Payout { principal: 1_253_333, interest: 224_903 })
+        );
+        assert_eq!(crank(created + 940 * day), Err(StakingError::PositionClosed.into()));
+    }
+
+    #[test]
+    fn test_renew_long_after_maturity() {
+        // Время после лока начисляется по старому тиру, новый лок начинается с момента продления.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        // Владелец позиции заодно админ пула.
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &owner);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        _process_set_tier_post_maturity_instruction(&program_id, &pool_config_account, &owners_account, 180, PostMaturityAccrual::Stop, 0, 0).unwrap();
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            180u16).unwrap();
+        let renew = |now: UnixTimestamp| {
+            _process_renew_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, now, 180, false)
+        };
+
+        // После лока ничего не начисляется, за 1000 дней только 180 дней под 15%.
+        renew(created + 1000 * day).unwrap();
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 75_000u64);
+        assert_eq!(staking_info.lock_start, created + 1000 * day);
+        // Повторные продления в той же транзакции не проходят, новый лок еще идет.
+        for _ in 0..4 {
+            assert_eq!(renew(created + 1000 * day), Err(StakingError::LockNotExpired.into()));
+        }
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 75_000u64);
+
+        assert_eq!(
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                created + 1180 * day,
+                WithdrawStrategy::CloseAccount),
+            Ok(Payout { principal: 1_000_000, interest: 150_000 })
+        );
+    }
+
+    #[test]
+    fn test_transfer_position() {
+        // Проценты, набежавшие до пополнения, не теряются и выплачиваются любой стратегией.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let new_owner = Pubkey::new_unique();
+        let mut new_owners_account_lamports = 0;
+        let mut new_owners_account_data = vec![0; 0];
+        let new_owners_account = AccountInfo::new(
+            &new_owner,
+            true,
+            false,
+            &mut new_owners_account_lamports,
+            &mut new_owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+        let mut not_signed_lamports = 0;
+        let mut not_signed_data = vec![0; 0];
+        let new_owners_account_not_signed = AccountInfo::new(
+            &new_owner,
+            false,
+            false,
+            &mut not_signed_lamports,
+            &mut not_signed_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            180u16).unwrap();
+        _process_set_auto_renew_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, true, true).unwrap();
+        let withdraw = |owners_account: &AccountInfo, now: UnixTimestamp| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                WithdrawStrategy::InterestOnly).and_then(|payout| payout.total())
+        };
+
+        assert_eq!(
+            _process_transfer_position_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, None, &owner, created),
+            Err(StakingError::InvalidNewHolder.into())
+        );
+        assert_eq!(
+            _process_transfer_position_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, None, &Pubkey::default(), created),
+            Err(StakingError::InvalidNewHolder.into())
+        );
+        assert_eq!(
+            _process_transfer_position_instruction(&program_id, &staking_account, &new_owners_account, &pool_config_account, None, &owner, created),
+            Err(StakingError::WrongHolder.into())
+        );
+        let mut unsigned_owner = owners_account.clone();
+        unsigned_owner.is_signer = false;
+        assert_eq!(
+            _process_transfer_position_instruction(&program_id, &staking_account, &unsigned_owner, &pool_config_account, None, &new_owner, created),
+            Err(StakingError::HolderNotSigner.into())
+        );
+        assert_eq!(
+            _process_transfer_position_instruction(
+                &program_id, &staking_account, &owners_account, &pool_config_account, Some(&new_owners_account_not_signed), &new_owner, created),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        // 90 дней под 15% переходят вместе с позицией.
+        _process_transfer_position_instruction(
+            &program_id, &staking_account, &owners_account, &pool_config_account, Some(&new_owners_account), &new_owner, created + 90 * day).unwrap();
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.holder, new_owner);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 37_500u64);
+        assert!(!staking_info.auto_renew);
+
+        assert_eq!(withdraw(&owners_account, created + 180 * day), Err(StakingError::WrongHolder.into()));
+        assert_eq!(withdraw(&new_owners_account, created + 180 * day), Ok(75_000u64));
+        assert_eq!(PoolConfig::load(&program_id, &pool_config_account).unwrap().interest_liability, 0);
+
+        // Проценты после лока, перенесенные передачей, учитываются в обязательствах пула.
+        _process_transfer_position_instruction(
+            &program_id, &staking_account, &new_owners_account, &pool_config_account, None, &owner, created + 270 * day).unwrap();
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 37_500u64);
+        assert_eq!(PoolConfig::load(&program_id, &pool_config_account).unwrap().interest_liability, 37_500);
+    }
+
+    #[test]
+    fn test_crank_compound() {
+        // Проценты, набежавшие до пополнения, не теряются и выплачиваются любой стратегией.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        // Холдер заодно и админ пула.
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &owner);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let keeper_token_account_key = Pubkey::new_unique();
+        let mut keeper_token_account_lamports = 0;
+        let mut keeper_token_account_data = token_account_data(&TEST_MINT, &Pubkey::new_unique(), 0);
+        let keeper_token_account = AccountInfo::new(
+            &keeper_token_account_key,
+            false,
+            true,
+            &mut keeper_token_account_lamports,
+            &mut keeper_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            360u16).unwrap();
+        let crank = |keeper_token_account: &AccountInfo, now: UnixTimestamp| {
+            _process_crank_compound_instruction(
+                &program_id,
+                &staking_account,
+                &pools_token_account,
+                keeper_token_account,
+                &pool_config_account,
+                now)
+        };
+
+        assert_eq!(crank(&keeper_token_account, created + 90 * day), Err(StakingError::DelegatedCompoundingDisabled.into()));
+        _process_set_delegated_compounding_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, true).unwrap();
+        assert_eq!(crank(&pools_token_account, created + 90 * day), Err(StakingError::SelfTransfer.into()));
+
+        assert_eq!(
+            _process_set_keeper_tip_instruction(&program_id, &pool_config_account, &owners_account, MAX_KEEPER_TIP_BPS + 1),
+            Err(StakingError::InvalidKeeperTip.into())
+        );
+        _process_set_keeper_tip_instruction(&program_id, &pool_config_account, &owners_account, 100).unwrap();
+
+        // 180 дней под 17%, 1% процентов уходит киперу.
+        assert_eq!(crank(&keeper_token_account, created + 180 * day), Ok(850u64));
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.token_amount, 1_084_150u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 0u64);
+        assert_eq!(staking_info.last_withdraw_date, created + 180 * day);
+        assert_eq!(crank(&keeper_token_account, created + 180 * day), Ok(0u64));
+
+        _process_set_delegated_compounding_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, false).unwrap();
+        assert_eq!(crank(&keeper_token_account, created + 270 * day), Err(StakingError::DelegatedCompoundingDisabled.into()));
+    }
+
+    #[test]
+    fn test_reward_reserve() {
+        // Проценты платятся только из внесенного через FundRewards резерва, тела позиций не трогаются.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        // Новый пул: ни тел, ни резерва, ни обязательств.
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config = PoolConfig::deserialize(&mut &pool_config_data[..]).unwrap();
+        pool_config.total_principal = 0;
+        pool_config.reward_reserve = 0;
+        pool_config.serialize(&mut &mut pool_config_data[..]).unwrap();
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let funder = Pubkey::new_unique();
+        let mut funder_lamports = 0;
+        let mut funder_data = vec![0; 0];
+        let funder_account = AccountInfo::new(
+            &funder,
+            true,
+            false,
+            &mut funder_lamports,
+            &mut funder_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let funder_token_account_key = Pubkey::new_unique();
+        let mut funder_token_account_lamports = 0;
+        let mut funder_token_account_data = token_account_data(&TEST_MINT, &funder, 1_000_000);
+        let funder_token_account = AccountInfo::new(
+            &funder_token_account_key,
+            false,
+            true,
+            &mut funder_token_account_lamports,
+            &mut funder_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let other_mint_token_account_key = Pubkey::new_unique();
+        let mut other_mint_token_account_lamports = 0;
+        let mut other_mint_token_account_data = token_account_data(&Pubkey::new_unique(), &funder, 1_000_000);
+        let other_mint_token_account = AccountInfo::new(
+            &other_mint_token_account_key,
+            false,
+            true,
+            &mut other_mint_token_account_lamports,
+            &mut other_mint_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        let totals = || {
+            let pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+            (pool_config.total_principal, pool_config.reward_reserve, pool_config.interest_liability)
+        };
+        let fund = |funder_token_account: &AccountInfo, amount: u64| {
+            _process_fund_rewards_instruction(
+                &program_id,
+                &pool_config_account,
+                &funder_account,
+                funder_token_account,
+                &pools_token_account,
+                amount)
+        };
+        let stake = || {
+            _process_staking_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                1_000_000u64,
+                created,
+                360u16)
+        };
+        let withdraw = |now: UnixTimestamp, withdraw_strategy: WithdrawStrategy| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                withdraw_strategy)
+        };
+
+        assert_eq!(fund(&funder_token_account, 0), Err(ProgramError::InvalidArgument));
+        assert_eq!(fund(&pools_token_account, 100_000), Err(StakingError::SelfTransfer.into()));
+        assert_eq!(fund(&other_mint_token_account, 100_000), Err(StakingError::MintMismatch.into()));
+
+        // За год под 17% пул обещает 170000, стейк принимается только когда они внесены.
+        assert_eq!(stake(), Err(StakingError::InterestNotCovered.into()));
+        fund(&funder_token_account, 100_000).unwrap();
+        assert_eq!(stake(), Err(StakingError::InterestNotCovered.into()));
+        fund(&funder_token_account, 100_000).unwrap();
+        stake().unwrap();
+        assert_eq!(totals(), (1_000_000, 200_000, 170_000));
+
+        // Выплата уменьшает и резерв, и обязательства.
+        assert_eq!(withdraw(created + 180 * day, WithdrawStrategy::InterestOnly), Ok(Payout { principal: 0, interest: 85_000 }));
+        assert_eq!(totals(), (1_000_000, 115_000, 85_000));
+
+        // После окончания лока обязательств нет, остаток резерва свободен.
+        assert_eq!(withdraw(created + 360 * day, WithdrawStrategy::Compound), Ok(Payout { principal: 0, interest: 0 }));
+        assert_eq!(totals(), (1_085_000, 30_000, 0));
+        assert_eq!(withdraw(created + 360 * day, WithdrawStrategy::Principal(85_000)), Ok(Payout { principal: 85_000, interest: 0 }));
+        assert_eq!(totals(), (1_000_000, 30_000, 0));
+
+        // Проценты после лока не обещаны заранее и платятся только если резерв есть.
+        assert_eq!(withdraw(created + 540 * day, WithdrawStrategy::CloseAccount), Err(StakingError::InsufficientRewardReserve.into()));
+        fund(&funder_token_account, 100_000).unwrap();
+        assert_eq!(withdraw(created + 540 * day, WithdrawStrategy::CloseAccount), Ok(Payout { principal: 1_000_000, interest: 85_000 }));
+        assert_eq!(totals(), (0, 45_000, 0));
+    }
+
+    #[test]
+    fn test_insolvency_policy() {
+        // Когда резерва не хватает, проценты выплачиваются частично, остаток остается долгом позиции.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let first_staking_account_key = Pubkey::new_unique();
+        let mut first_staking_account_lamports = 0;
+        let mut first_staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let first_staking_account = AccountInfo::new(
+            &first_staking_account_key,
+            false,
+            true,
+            &mut first_staking_account_lamports,
+            &mut first_staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let second_staking_account_key = Pubkey::new_unique();
+        let mut second_staking_account_lamports = 0;
+        let mut second_staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let second_staking_account = AccountInfo::new(
+            &second_staking_account_key,
+            false,
+            true,
+            &mut second_staking_account_lamports,
+            &mut second_staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        // Холдер заодно и админ пула, пул новый.
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &owner);
+        let mut pool_config = PoolConfig::deserialize(&mut &pool_config_data[..]).unwrap();
+        pool_config.total_principal = 0;
+        pool_config.reward_reserve = 0;
+        pool_config.serialize(&mut &mut pool_config_data[..]).unwrap();
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let funder_token_account_key = Pubkey::new_unique();
+        let mut funder_token_account_lamports = 0;
+        let mut funder_token_account_data = token_account_data(&TEST_MINT, &owner, 1_000_000);
+        let funder_token_account = AccountInfo::new(
+            &funder_token_account_key,
+            false,
+            true,
+            &mut funder_token_account_lamports,
+            &mut funder_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        let totals = || {
+            let pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+            (pool_config.total_principal, pool_config.reward_reserve, pool_config.interest_liability)
+        };
+        let fund = |amount: u64| {
+            _process_fund_rewards_instruction(
+                &program_id,
+                &pool_config_account,
+                &owners_account,
+                &funder_token_account,
+                &pools_token_account,
+                amount)
+        };
+        let set_policy = |insolvency_policy: InsolvencyPolicy| {
+            _process_set_insolvency_policy_instruction(&program_id, &pool_config_account, &owners_account, insolvency_policy)
+        };
+        let withdraw = |staking_account: &AccountInfo, now: UnixTimestamp, withdraw_strategy: WithdrawStrategy| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                withdraw_strategy)
+        };
+
+        fund(340_000).unwrap();
+        for staking_account in [&first_staking_account, &second_staking_account] {
+            _process_staking_instruction(
+                &program_id,
+                staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                1_000_000u64,
+                created,
+                360u16).unwrap();
+        }
+        assert_eq!(withdraw(&first_staking_account, created + 360 * day, WithdrawStrategy::InterestOnly), Ok(Payout { principal: 0, interest: 170_000 }));
+        assert_eq!(totals(), (2_000_000, 170_000, 170_000));
+
+        // Проценты после лока не покрыты резервом, по умолчанию инструкция падает.
+        assert_eq!(
+            withdraw(&first_staking_account, created + 540 * day, WithdrawStrategy::InterestOnly),
+            Err(StakingError::InsufficientRewardReserve.into())
+        );
+
+        // Кто первый пришел, тот и получил, даже обещанное второй позиции.
+        set_policy(InsolvencyPolicy::FirstCome).unwrap();
+        assert_eq!(withdraw(&first_staking_account, created + 540 * day, WithdrawStrategy::InterestOnly), Ok(Payout { principal: 0, interest: 85_000 }));
+        assert_eq!(totals(), (2_000_000, 85_000, 170_000));
+
+        // Пропорционально: 85000 * 85000 / (170000 + 85000). Позиция с долгом не закрывается.
+        set_policy(InsolvencyPolicy::ProRata).unwrap();
+        assert_eq!(
+            withdraw(&first_staking_account, created + 720 * day, WithdrawStrategy::CloseAccount),
+            Ok(Payout { principal: 1_000_000, interest: 28_333 })
+        );
+        let staking_info = StakingAccount::try_from_slice(&first_staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.closed, false);
+        assert_eq!(staking_info.token_amount, 0u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 56_667u64);
+        assert_eq!(totals(), (1_000_000, 56_667, 226_667));
+
+        // 340000 * 56667 / (56667 + 340000).
+        assert_eq!(withdraw(&second_staking_account, created + 720 * day, WithdrawStrategy::InterestOnly), Ok(Payout { principal: 0, interest: 48_571 }));
+        let staking_info = StakingAccount::try_from_slice(&second_staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 291_429u64);
+        assert_eq!(totals(), (1_000_000, 8_096, 348_096));
+
+        // После пополнения долг выплачивается, и позиция наконец закрывается.
+        fund(340_000).unwrap();
+        assert_eq!(withdraw(&first_staking_account, created + 720 * day, WithdrawStrategy::CloseAccount), Ok(Payout { principal: 0, interest: 56_667 }));
+        let staking_info = StakingAccount::try_from_slice(&first_staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.closed, true);
+        assert_eq!(totals(), (1_000_000, 291_429, 291_429));
+
+        assert_eq!(
+            _process_set_insolvency_policy_instruction(&program_id, &pool_config_account, &funder_token_account, InsolvencyPolicy::Revert),
+            Err(StakingError::NotPoolAdmin.into())
+        );
+    }
+
+    #[test]
+    fn test_pause() {
+        // Админ может остановить стейки и выплаты, выход с телом при этом остается.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        // Холдер заодно и админ пула.
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &owner);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        let set_pause = |admin_account: &AccountInfo, paused: u8| {
+            _process_set_pause_instruction(&program_id, &pool_config_account, admin_account, paused)
+        };
+        let stake = || {
+            _process_staking_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                1_000_000u64,
+                created,
+                360u16)
+        };
+        let withdraw = |now: UnixTimestamp| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                WithdrawStrategy::InterestOnly)
+        };
+        let emergency_exit = |now: UnixTimestamp| {
+            _process_emergency_exit_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &pools_token_account,
+                &pool_config_account,
+                now)
+        };
+
+        assert_eq!(set_pause(&owners_account, 8), Err(ProgramError::InvalidArgument));
+        assert_eq!(set_pause(&staking_account, PAUSE_ALL), Err(StakingError::NotPoolAdmin.into()));
+        set_pause(&owners_account, PAUSE_STAKING).unwrap();
+        assert_eq!(stake(), Err(StakingError::InstructionPaused.into()));
+        set_pause(&owners_account, 0).unwrap();
+        stake().unwrap();
+
+        assert_eq!(emergency_exit(created + 90 * day), Err(StakingError::PoolNotPaused.into()));
+        set_pause(&owners_account, PAUSE_ALL).unwrap();
+        assert_eq!(withdraw(created + 90 * day), Err(StakingError::InstructionPaused.into()));
+
+        // Тело возвращается целиком и без штрафа, проценты за 90 дней под 17% остаются долгом.
+        assert_eq!(emergency_exit(created + 90 * day), Ok(1_000_000u64));
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.closed, false);
+        assert_eq!(staking_info.token_amount, 0u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 42_500u64);
+        assert_eq!(PoolConfig::load(&program_id, &pool_config_account).unwrap().total_principal, TEST_POOL_TOTALS);
+
+        // Настройки позиции тоже на паузе.
+        assert_eq!(
+            _process_set_auto_renew_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, true, false),
+            Err(StakingError::InstructionPaused.into())
+        );
+        assert_eq!(
+            _process_set_delegated_compounding_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, true),
+            Err(StakingError::InstructionPaused.into())
+        );
+        assert_eq!(
+            _process_transfer_position_instruction(
+                &program_id, &staking_account, &owners_account, &pool_config_account, None, &Pubkey::new_unique(), created + 90 * day),
+            Err(StakingError::InstructionPaused.into())
+        );
+
+        set_pause(&owners_account, 0).unwrap();
+        assert_eq!(withdraw(created + 120 * day), Ok(Payout { principal: 0, interest: 42_500 }));
+
+        // Позицию без тела можно закрыть, не дожидаясь конца старого лока.
+        let emptied_position = staking_account.data.borrow().to_vec();
+        assert_eq!(
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                created + 130 * day,
+                WithdrawStrategy::CloseAccount),
+            Ok(Payout { principal: 0, interest: 0 })
+        );
+        assert!(position_wiped(&staking_account));
+        staking_account.data.borrow_mut().copy_from_slice(&emptied_position);
+
+        // Когда позиции ничего не должны, она закрывается.
+        set_pause(&owners_account, PAUSE_INTEREST).unwrap();
+        assert_eq!(emergency_exit(created + 150 * day), Ok(0u64));
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.closed, true);
+    }
+
+    #[test]
+    fn test_reward_vault() {
+        // Проценты, набежавшие до пополнения, не теряются и выплачиваются любой стратегией.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &owner);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let reward_mint = Pubkey::new_unique();
+        let reward_vault_key = Pubkey::new_unique();
+        let mut reward_vault_lamports = 0;
+        let mut reward_vault_data = token_account_data(&reward_mint, &pool_owner, 0);
+        let reward_vault_account = AccountInfo::new(
+            &reward_vault_key,
+            false,
+            true,
+            &mut reward_vault_lamports,
+            &mut reward_vault_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+        let mut pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+        pool_config.reward_mint = reward_mint;
+        pool_config.reward_vault = reward_vault_key;
+        // Штраф от созданного раньше тира.
+        pool_config.find_tier_mut(180).unwrap().early_unstake_penalty_bps = 1_000;
+        pool_config.save(&pool_config_account).unwrap();
+        assert!(pool_config.has_reward_vault());
+        // Без казны штрафу некуда уйти.
+        assert_eq!(
+            _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &owners_account, 360, 500, false),
+            Err(StakingError::TreasuryRequired.into())
+        );
+        _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &owners_account, 360, 0, true).unwrap();
+        let mut penalty_tier = default_tiers()[0].clone();
+        penalty_tier.duration = 90;
+        penalty_tier.early_unstake_penalty_bps = 500;
+        assert_eq!(
+            _process_add_tier_instruction(&program_id, &pool_config_account, &owners_account, penalty_tier),
+            Err(StakingError::TreasuryRequired.into())
+        );
+        assert_eq!(
+            pool_config.check_reward_vault(&program_id, &pools_token_account),
+            Err(StakingError::InvalidPoolVault.into())
+        );
+        pool_config.check_reward_vault(&program_id, &reward_vault_account).unwrap();
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            180u16).unwrap();
+        let withdraw = |now: UnixTimestamp, withdraw_strategy: WithdrawStrategy| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                withdraw_strategy)
+        };
+
+        assert_eq!(
+            _process_early_unstake_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &pools_token_account,
+                &pool_config_account,
+                created + 90 * day),
+            Err(StakingError::TreasuryRequired.into())
+        );
+
+        // Проценты в другом токене нельзя добавить к телу.
+        assert_eq!(withdraw(created + 90 * day, WithdrawStrategy::Compound), Err(StakingError::CompoundNotSupported.into()));
+        assert_eq!(
+            _process_renew_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, created + 180 * day, 0, true),
+            Err(StakingError::CompoundNotSupported.into())
+        );
+        _process_set_delegated_compounding_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, true).unwrap();
+        assert_eq!(
+            _process_crank_compound_instruction(
+                &program_id,
+                &staking_account,
+                &pools_token_account,
+                &owners_token_account,
+                &pool_config_account,
+                created + 90 * day),
+            Err(StakingError::CompoundNotSupported.into())
+        );
+
+        assert_eq!(withdraw(created + 90 * day, WithdrawStrategy::InterestOnly), Ok(Payout { principal: 0, interest: 37_500 }));
+        assert_eq!(
+            withdraw(created + 180 * day, WithdrawStrategy::CloseAccount),
+            Ok(Payout { principal: 1_000_000, interest: 37_500 })
+        );
+    }
+
+    #[test]
+    fn test_reward_streams() {
+        // Проценты, набежавшие до пополнения, не теряются и выплачиваются любой стратегией.
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let staking_account_key = Pubkey::new_unique();
+        let mut staking_account_lamports = 0;
+        let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
+        let staking_account = AccountInfo::new(
+            &staking_account_key,
+            false,
+            true,
+            &mut staking_account_lamports,
+            &mut staking_account_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let owners_token_account_key = Pubkey::new_unique();
+        let mut owners_token_account_lamports = 0;
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
+        let owners_token_account = AccountInfo::new(
+            &owners_token_account_key,
+            false,
+            true,
+            &mut owners_token_account_lamports,
+            &mut owners_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let mut pools_token_account_lamports = 0;
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
+        let pools_token_account = AccountInfo::new(
+            &TEST_VAULT,
+            false,
+            true,
+            &mut pools_token_account_lamports,
+            &mut pools_token_account_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default()
+        );
+
+        let mut owners_account_lamports = 0;
+        let mut owners_account_data = vec![0; 0];
+        let owners_account = AccountInfo::new(
+            &owner,
+            true,
+            false,
+            &mut owners_account_lamports,
+            &mut owners_account_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        // Холдер заодно и админ пула.
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &owner);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let stream_mint = Pubkey::new_unique();
+        let mut stream_mint_lamports = 0;
+        let mut stream_mint_data = mint_data();
+        let stream_mint_account = AccountInfo::new(
+            &stream_mint,
+            false,
+            false,
+            &mut stream_mint_lamports,
+            &mut stream_mint_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+        let stream_vault_key = Pubkey::new_unique();
+        let mut stream_vault_lamports = 0;
+        let mut stream_vault_data = token_account_data(&stream_mint, &pool_owner, 0);
+        let stream_vault_account = AccountInfo::new(
+            &stream_vault_key,
+            false,
+            true,
+            &mut stream_vault_lamports,
+            &mut stream_vault_data,
+            &SPL_TOKEN_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        let add_stream = |start: UnixTimestamp, end: UnixTimestamp, rates: Vec<StreamRate>| {
+            _process_add_reward_stream_instruction(
+                &program_id,
+                &pool_config_account,
+                &owners_account,
+                &stream_mint_account,
+                &stream_vault_account,
+                start,
+                end,
+                rates)
+        };
+        let remove_stream = |id: u64, now: UnixTimestamp| {
+            _process_remove_reward_stream_instruction(&program_id, &pool_config_account, &owners_account, id, now)
+        };
+        let claim = |now: UnixTimestamp| {
+            _process_claim_rewards_instruction(&program_id, &staking_account, &owners_account, &pool_config_account, now)
+        };
+        let rate = |duration: u16, numerator: u64| StreamRate { duration, numerator, denominator: 100 };
+
+        assert_eq!(add_stream(created, created, vec![rate(360, 10)]), Err(StakingError::InvalidRewardStream.into()));
+        assert_eq!(add_stream(created, created + day, vec![]), Err(StakingError::InvalidRewardStream.into()));
+        assert_eq!(
+            add_stream(created, created + day, vec![rate(360, 10), rate(360, 20)]),
+            Err(StakingError::InvalidRewardStream.into())
+        );
+        add_stream(created, created + 180 * day, vec![rate(180, 20), rate(360, 10)]).unwrap();
+
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            360u16).unwrap();
+
+        // 90 дней по 10% годовых в токене стрима.
+        assert_eq!(claim(created + 90 * day), Ok([25_000, 0, 0, 0]));
+        assert_eq!(claim(created + 90 * day), Ok([0; MAX_REWARD_STREAMS]));
+        // Пополнение сначала начисляет награды по старому телу.
+        _process_add_stake_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created + 135 * day).unwrap();
+        // 45 дней на 1000000 и 45 дней на 2000000, после окончания стрима ничего не начисляется.
+        assert_eq!(claim(created + 200 * day), Ok([37_500, 0, 0, 0]));
+
+        assert_eq!(remove_stream(1, created + 209 * day), Err(StakingError::RewardStreamActive.into()));
+        assert_eq!(remove_stream(2, created + 210 * day), Err(StakingError::RewardStreamNotFound.into()));
+        remove_stream(1, created + 210 * day).unwrap();
+
+        // Новый стрим в том же слоте начисляет с нуля.
+        add_stream(created + 210 * day, created + 300 * day, vec![rate(360, 5)]).unwrap();
+        assert_eq!(claim(created + 300 * day), Ok([25_000, 0, 0, 0]));
+        let pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+        assert_eq!(pool_config.reward_streams[0].id, 2);
+        assert_eq!(pool_config.reward_streams[0].rates[0], rate(360, 5));
+
+        for _ in 1..MAX_REWARD_STREAMS {
+            add_stream(created, created + day, vec![rate(360, 1)]).unwrap();
+        }
+        assert_eq!(add_stream(created, created + day, vec![rate(360, 1)]), Err(StakingError::TooManyRewardStreams.into()));
+
+        // Невостребованные награды не сгорают при закрытии, позиция без тела ждет ClaimRewards.
+        let close = |now: UnixTimestamp| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                WithdrawStrategy::CloseAccount).map(|payout| payout.principal)
+        };
+        assert_eq!(close(created + 600 * day), Ok(2_000_000u64));
+        assert!(!position_wiped(&staking_account));
+        let rewards = claim(created + 600 * day).unwrap();
+        assert!(rewards[1..].iter().all(|reward| *reward > 0));
+        assert_eq!(close(created + 600 * day), Ok(0u64));
+        assert!(position_wiped(&staking_account));
+    }
 
     #[test]
-    fn test_initialize_staking_account() {
-        // Проверка инициализации staking-PDA аккаунта.
-        // Нужные значения сохраняются + не допускается повторная инициализация.
+    fn test_add_stake() {
+        // Проценты, набежавшие до пополнения, не теряются и выплачиваются любой стратегией.
         let program_id = Pubkey::new_unique();
-    
         let owner = Pubkey::new_unique();
 
         let staking_account_key = Pubkey::new_unique();
         let mut staking_account_lamports = 0;
         let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
-        let mut staking_account = AccountInfo::new(
+        let staking_account = AccountInfo::new(
             &staking_account_key,
             false,
             true,
@@ -371,7 +5650,7 @@ mod test {
 
         let owners_token_account_key = Pubkey::new_unique();
         let mut owners_token_account_lamports = 0;
-        let mut owners_token_account_data = vec![0; 0];
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
         let owners_token_account = AccountInfo::new(
             &owners_token_account_key,
             false,
@@ -383,11 +5662,11 @@ mod test {
             Epoch::default(),
         );
 
-        let (pools_token_account_key, bump_seed) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
         let mut pools_token_account_lamports = 0;
-        let mut pools_token_account_data = vec![0; 0];
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
         let pools_token_account = AccountInfo::new(
-            &pools_token_account_key,
+            &TEST_VAULT,
             false,
             true,
             &mut pools_token_account_lamports,
@@ -410,75 +5689,106 @@ mod test {
             Epoch::default(),
         );
 
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
 
-        let instruction_data: Vec<u8> = Vec::new();
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        let add_stake = |now: UnixTimestamp| {
+            _process_add_stake_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                1_000_000u64,
+                now)
+        };
+        let withdraw = |now: UnixTimestamp, withdraw_strategy: WithdrawStrategy| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                withdraw_strategy).and_then(|payout| payout.total())
+        };
 
-        assert_eq!(
-            StakingAccount::try_from_slice(&staking_account.data.borrow())
-                .unwrap()
-                .initialized,
-            false
-        );
-        
-        let result = _process_staking_instruction(
-            &program_id, 
-            &mut staking_account,
+        assert_eq!(add_stake(created), Err(StakingError::StakingAccountNotInitialized.into()));
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
             &owners_account,
             &owners_token_account,
             &pools_token_account,
-            12u64,
-            1234567890 as UnixTimestamp,
-            360u16,
-            bump_seed).unwrap();
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            360u16).unwrap();
 
-        let staking_account_initialized = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
-        assert_eq!(staking_account_initialized.initialized, true);
-        assert_eq!(staking_account_initialized.holder, owner);
-        assert_eq!(staking_account_initialized.created, 1234567890 as UnixTimestamp);
-        assert_eq!(staking_account_initialized.token_amount, 12u64);
-        assert_eq!(staking_account_initialized.extra_not_withdrawn_tokens, 0u64);
-        assert_eq!(staking_account_initialized.last_withdraw_date, 1234567890 as UnixTimestamp);
+        // 180 дней под 17% на 1000000.
+        add_stake(created + 180 * day).unwrap();
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.token_amount, 2_000_000u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 85_000u64);
+        assert_eq!(staking_info.created, created);
+        assert_eq!(staking_info.lock_start, created + 180 * day);
+        // Тир не менялся, снимок тот же.
+        assert_eq!(staking_info.interest_numerator, 17);
+        assert_eq!(staking_info.early_unstake_penalty_bps, 0);
 
-        let second_invocation_result = _process_staking_instruction(
-            &program_id, 
-            &mut staking_account,
-            &owners_account,
-            &owners_token_account,
-            &pools_token_account,
-            12u64,
-            1234567890 as UnixTimestamp,
-            360u16,
-            bump_seed);
-        assert_eq!(second_invocation_result.is_ok(), false);
+        // 85000 перенесенных + 90 дней на 2000000.
+        assert_eq!(withdraw(created + 270 * day, WithdrawStrategy::InterestOnly), Ok(170_000u64));
+        assert_eq!(withdraw(created + 360 * day, WithdrawStrategy::Compound), Ok(0u64));
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.token_amount, 2_085_000u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 0u64);
+
+        // Пополнение продлевает лок.
+        assert_eq!(
+            withdraw(created + 400 * day, WithdrawStrategy::CloseAccount),
+            Err(StakingError::LockNotExpired.into())
+        );
+        assert_eq!(withdraw(created + 540 * day, WithdrawStrategy::CloseAccount), Ok(2_262_225u64));
+        assert_eq!(add_stake(created + 540 * day), Err(StakingError::PositionClosed.into()));
     }
 
     #[test]
-    fn test_wrong_owner() {
-        // Это довольно странный тест, но все так делают почему-то...
-        // Мне страшно идти на поводу своих принципов и не делать его,
-        // даже если я не вижу в этом смысла.
+    fn test_add_stake_after_tier_change() {
+        // Пополнение оставляет позиции прежние ставку, штраф и политику после лока, изменения тира ее не касаются.
         let program_id = Pubkey::new_unique();
-        let other_program_id = Pubkey::new_unique();
-    
         let owner = Pubkey::new_unique();
 
         let staking_account_key = Pubkey::new_unique();
         let mut staking_account_lamports = 0;
         let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
-        let mut staking_account = AccountInfo::new(
+        let staking_account = AccountInfo::new(
             &staking_account_key,
             false,
             true,
             &mut staking_account_lamports,
             &mut staking_account_data,
-            &other_program_id,
+            &program_id,
             false,
             Epoch::default(),
         );
 
         let owners_token_account_key = Pubkey::new_unique();
         let mut owners_token_account_lamports = 0;
-        let mut owners_token_account_data = vec![0; 0];
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 2_000_000);
         let owners_token_account = AccountInfo::new(
             &owners_token_account_key,
             false,
@@ -490,18 +5800,18 @@ mod test {
             Epoch::default(),
         );
 
-        let (pools_token_account_key, bump_seed) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
         let mut pools_token_account_lamports = 0;
-        let mut pools_token_account_data = vec![0; 0];
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
         let pools_token_account = AccountInfo::new(
-            &pools_token_account_key,
+            &TEST_VAULT,
             false,
             true,
             &mut pools_token_account_lamports,
             &mut pools_token_account_data,
             &SPL_TOKEN_PROGRAM_ID,
             false,
-            Epoch::default(),
+            Epoch::default()
         );
 
         let mut owners_account_lamports = 0;
@@ -517,101 +5827,515 @@ mod test {
             Epoch::default(),
         );
 
-        let result = _process_staking_instruction(
-            &program_id, 
-            &mut staking_account,
+        // Владелец позиции заодно админ пула.
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &owner);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
             &owners_account,
             &owners_token_account,
             &pools_token_account,
-            12u64,
-            1234567890 as UnixTimestamp,
-            360u16,
-            bump_seed);
-        assert_eq!(result.is_ok(), false);
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            360u16).unwrap();
+
+        _process_update_tier_rate_instruction(&program_id, &pool_config_account, &owners_account, 360, 10, 100).unwrap();
+        _process_set_tier_penalty_instruction(&program_id, &pool_config_account, &owners_account, 360, 500, false).unwrap();
+        _process_set_tier_post_maturity_instruction(&program_id, &pool_config_account, &owners_account, 360, PostMaturityAccrual::Stop, 0, 0).unwrap();
+
+        // 180 дней под старые 17% на 1000000.
+        _process_add_stake_instruction(
+            &program_id,
+            &staking_account,
+            &owners_account,
+            &owners_token_account,
+            &pools_token_account,
+            &pool_config_account,
+            1_000_000u64,
+            created + 180 * day).unwrap();
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.token_amount, 2_000_000u64);
+        assert_eq!(staking_info.extra_not_withdrawn_tokens, 85_000u64);
+        assert_eq!(staking_info.interest_numerator, 17);
+        assert_eq!(staking_info.interest_denominator, 100);
+        assert_eq!(staking_info.early_unstake_penalty_bps, 0);
+        assert_ne!(staking_info.post_maturity_accrual, PostMaturityAccrual::Stop);
+
+        // 85000 перенесенных + 360 дней под старые 17% на 2000000.
+        assert_eq!(
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                created + 540 * day,
+                WithdrawStrategy::CloseAccount),
+            Ok(Payout { principal: 2_000_000, interest: 425_000 })
+        );
+    }
+
+    #[test]
+    fn test_post_maturity_accrual() {
+        let day = 24 * 3600;
+        let created = 1234567890 as UnixTimestamp;
+        let new_position = |post_maturity_accrual: PostMaturityAccrual| {
+            let mut position = StakingAccount::try_from_slice(&[0; STAKING_ACCOUNT_SIZE]).unwrap();
+            position.initialized = true;
+            position.created = created;
+            position.lock_start = created;
+            position.last_withdraw_date = created;
+            position.duration = 180;
+            position.token_amount = 1_000_000;
+            position.interest_numerator = 15;
+            position.interest_denominator = 100;
+            position.post_maturity_accrual = post_maturity_accrual;
+            position.flexible_interest_numerator = 3;
+            position.flexible_interest_denominator = 100;
+            position
+        };
+
+        // 180 дней под 15% и 180 дней по политике тира.
+        assert_eq!(new_position(PostMaturityAccrual::FullRate).accrue_interest(created + 360 * day), Ok(150_000u64));
+        assert_eq!(new_position(PostMaturityAccrual::Stop).accrue_interest(created + 360 * day), Ok(75_000u64));
+        assert_eq!(new_position(PostMaturityAccrual::Flexible).accrue_interest(created + 360 * day), Ok(90_000u64));
+
+        // После окончания лока Stop больше ничего не начисляет.
+        let mut position = new_position(PostMaturityAccrual::Stop);
+        position.accrue_interest(created + 200 * day).unwrap();
+        assert_eq!(position.accrue_interest(created + 400 * day), Ok(0u64));
+        assert_eq!(position.last_withdraw_date, created + 400 * day);
+
+        // Период, на который пришлось окончание лока, начисляется уже по политике после лока.
+        position.lock_start = created + 30;
+        position.last_withdraw_date = created;
+        assert_eq!(position.accrue_interest(created + 180 * day + 60), Ok(75_000u64));
     }
 
-    // #[test]
-    // fn test_wrong_pool_id() {
-    //     let program_id = Pubkey::new_unique();    
-    //     let owner = Pubkey::new_unique();
-
-    //     let staking_account_key = Pubkey::new_unique();
-    //     let mut staking_account_lamports = 0;
-    //     let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
-    //     let mut staking_account = AccountInfo::new(
-    //         &staking_account_key,
-    //         false,
-    //         true,
-    //         &mut staking_account_lamports,
-    //         &mut staking_account_data,
-    //         &program_id,
-    //         false,
-    //         Epoch::default(),
-    //     );
-
-    //     let owners_token_account_key = Pubkey::new_unique();
-    //     let mut owners_token_account_lamports = 0;
-    //     let mut owners_token_account_data = vec![0; 0];
-    //     let owners_token_account = AccountInfo::new(
-    //         &owners_token_account_key,
-    //         false,
-    //         true,
-    //         &mut owners_token_account_lamports,
-    //         &mut owners_token_account_data,
-    //         &SPL_TOKEN_PROGRAM_ID,
-    //         false,
-    //         Epoch::default(),
-    //     );
-
-    //     let (pools_token_account_key, bump_seed) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
-    //     let mut pools_token_account_lamports = 0;
-    //     let mut pools_token_account_data = vec![0; 0];
-    //     let pools_token_account = AccountInfo::new(
-    //         &pools_token_account_key,
-    //         false,
-    //         true,
-    //         &mut pools_token_account_lamports,
-    //         &mut pools_token_account_data,
-    //         &SPL_TOKEN_PROGRAM_ID,
-    //         false,
-    //         Epoch::default()
-    //     );
-
-    //     let mut owners_account_lamports = 0;
-    //     let mut owners_account_data = vec![0; 0];
-    //     let owners_account = AccountInfo::new(
-    //         &owner,
-    //         true,
-    //         false,
-    //         &mut owners_account_lamports,
-    //         &mut owners_account_data,
-    //         &SYSTEM_PROGRAM_ID,
-    //         false,
-    //         Epoch::default(),
-    //     );
-
-    //     let result = _process_staking_instruction(
-    //         &program_id, 
-    //         &mut staking_account,
-    //         &owners_account,
-    //         &owners_token_account,
-    //         &pools_token_account,
-    //         12u64,
-    //         1234567890 as UnixTimestamp,
-    //         360u16,
-    //         bump_seed - 1);
-    //     assert_eq!(result.is_ok(), false);
-    // }
+    #[test]
+    fn test_error_codes() {
+        // Коды ошибок - часть интерфейса для клиентов, их нельзя менять.
+        assert_eq!(ProgramError::from(StakingError::PoolAlreadyInitialized), ProgramError::Custom(0));
+        assert_eq!(ProgramError::from(StakingError::WrongHolder), ProgramError::Custom(11));
+        assert_eq!(ProgramError::from(StakingError::LockNotExpired), ProgramError::Custom(13));
+        assert_eq!(ProgramError::from(StakingError::InvalidTokenAccount), ProgramError::Custom(18));
+        assert_eq!(ProgramError::from(StakingError::SelfTransfer), ProgramError::Custom(22));
+        assert_eq!(ProgramError::from(StakingError::InvalidPoolAuthority), ProgramError::Custom(23));
+        assert_eq!(ProgramError::from(StakingError::AutoRenewDisabled), ProgramError::Custom(28));
+        assert_eq!(ProgramError::from(StakingError::InvalidNewHolder), ProgramError::Custom(29));
+        assert_eq!(ProgramError::from(StakingError::InvalidKeeperTip), ProgramError::Custom(31));
+        assert_eq!(ProgramError::from(StakingError::CompoundNotSupported), ProgramError::Custom(32));
+        assert_eq!(ProgramError::from(StakingError::RewardStreamActive), ProgramError::Custom(36));
+        assert_eq!(ProgramError::from(StakingError::InsufficientRewardReserve), ProgramError::Custom(37));
+        assert_eq!(ProgramError::from(StakingError::InterestNotCovered), ProgramError::Custom(38));
+        assert_eq!(ProgramError::from(StakingError::PoolNotPaused), ProgramError::Custom(40));
+        assert_eq!(ProgramError::from(StakingError::NotUpgradeAuthority), ProgramError::Custom(41));
+        assert_eq!(ProgramError::from(StakingError::TreasuryRequired), ProgramError::Custom(42));
+        assert_eq!(ProgramError::from(StakingError::InvalidStakingAccountAddress), ProgramError::Custom(24));
+        assert_eq!(ProgramError::from(StakingError::PositionClosed), ProgramError::Custom(25));
+        assert_eq!(ProgramError::from(StakingError::InvalidTreasury), ProgramError::Custom(26));
+        assert_eq!(StakingError::from_code(13), Some(StakingError::LockNotExpired));
+        assert_eq!(StakingError::from_code(u32::MAX), None);
+        assert_eq!(
+            StakingError::from_program_error(&ProgramError::Custom(11)),
+            Some(StakingError::WrongHolder)
+        );
+        assert_eq!(StakingError::from_program_error(&ProgramError::InvalidAccountData), None);
+    }
 
     #[test]
-    fn test_wrong_duration() {
-        let program_id = Pubkey::new_unique();    
+    fn test_unpacking_initialize_pool() {
+        let mut data = vec![
+            4, 1, 2,
+            0xb4, 0x00, 15, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0,
+            0x68, 0x01, 17, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0];
+        let instruction = Instruction::unpack(&data).unwrap();
+        assert_eq!(instruction, Instruction::InitializePool { tiers: default_tiers() });
+
+        data.pop();
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_unpacking_tier_instructions() {
+        let data = [5, 1, 0x5a, 0x00, 12, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::AddTier { tier: Tier { duration: 90, interest_numerator: 12, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: true, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 } }
+        );
+        let data = [6, 1, 0xb4, 0x00, 16, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::UpdateTierRate { duration: 180, interest_numerator: 16, interest_denominator: 100 }
+        );
+        let data = [7, 1, 0xb4, 0x00];
+        assert_eq!(Instruction::unpack(&data).unwrap(), Instruction::RetireTier { duration: 180 });
+        let data = [7, 1, 0xb4];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        let data = [5, 2, 0x5a, 0x00, 12, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 0xe8, 0x03, 0];
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::AddTier { tier: Tier { duration: 90, interest_numerator: 12, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 1000, early_unstake_forfeits_interest: false, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 } }
+        );
+        let data = [5, 3, 0x5a, 0x00, 12, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::AddTier { tier: Tier { duration: 90, interest_numerator: 12, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: false, post_maturity_accrual: PostMaturityAccrual::Flexible, flexible_interest_numerator: 3, flexible_interest_denominator: 100 } }
+        );
+        let data = [16, 1, 0xb4, 0x00, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::SetTierPostMaturity { duration: 180, post_maturity_accrual: PostMaturityAccrual::Stop, flexible_interest_numerator: 0, flexible_interest_denominator: 0 }
+        );
+        // Неизвестная политика.
+        let data = [16, 1, 0xb4, 0x00, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        let data = [9, 1, 0xb4, 0x00, 0xf4, 0x01, 1];
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::SetTierPenalty { duration: 180, early_unstake_penalty_bps: 500, early_unstake_forfeits_interest: true }
+        );
+        // bool кодируется только 0 или 1.
+        let data = [9, 1, 0xb4, 0x00, 0xf4, 0x01, 2];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(Instruction::unpack(&[8, 1]).unwrap(), Instruction::EarlyUnstake);
+        assert_eq!(Instruction::unpack(&[10, 1]).unwrap(), Instruction::SetTreasury);
+        assert_eq!(Instruction::unpack(&[11, 1, 5, 0, 0, 0, 0, 0, 0, 0]).unwrap(), Instruction::AddStake { amount: 5 });
+        assert_eq!(Instruction::unpack(&[12, 1, 5, 0, 0, 0, 0, 0, 0, 0]).unwrap(), Instruction::WithdrawPrincipal { amount: 5 });
+        assert_eq!(Instruction::unpack(&[13, 1, 1, 0]).unwrap(), Instruction::SetAutoRenew { auto_renew: true, compound: false });
+        assert_eq!(Instruction::unpack(&[14, 1, 0, 0, 1]).unwrap(), Instruction::Renew { duration: 0, compound: true });
+        assert_eq!(Instruction::unpack(&[15, 1]).unwrap(), Instruction::CrankRenew);
+        let mut data = vec![17, 1];
+        data.extend_from_slice(&[7; 32]);
+        data.push(1);
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::TransferPosition { new_holder: Pubkey::new_from_array([7; 32]), new_holder_signs: true }
+        );
+        assert_eq!(Instruction::unpack(&data[..33]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(Instruction::unpack(&[18, 1, 1]).unwrap(), Instruction::SetDelegatedCompounding { enabled: true });
+        assert_eq!(Instruction::unpack(&[19, 1, 0x64, 0x00]).unwrap(), Instruction::SetKeeperTip { keeper_tip_bps: 100 });
+        assert_eq!(Instruction::unpack(&[20, 1]).unwrap(), Instruction::CrankCompound);
+        assert_eq!(Instruction::unpack(&[21, 1]).unwrap(), Instruction::ClaimRewards);
+        assert_eq!(Instruction::unpack(&[23, 1, 2, 0, 0, 0, 0, 0, 0, 0]).unwrap(), Instruction::RemoveRewardStream { id: 2 });
+        let mut data = vec![22, 1, 10, 0, 0, 0, 0, 0, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 1, 0x68, 0x01];
+        data.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            Instruction::unpack(&data).unwrap(),
+            Instruction::AddRewardStream { start: 10, end: 20, rates: vec![StreamRate { duration: 360, numerator: 5, denominator: 100 }] }
+        );
+    }
+
+    #[test]
+    fn test_tier_management() {
+        let program_id = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &admin);
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            true,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let mut admin_lamports = 0;
+        let mut admin_data = vec![0; 0];
+        let admin_account = AccountInfo::new(
+            &admin,
+            true,
+            false,
+            &mut admin_lamports,
+            &mut admin_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let stranger = Pubkey::new_unique();
+        let mut stranger_lamports = 0;
+        let mut stranger_data = vec![0; 0];
+        let stranger_account = AccountInfo::new(
+            &stranger,
+            true,
+            false,
+            &mut stranger_lamports,
+            &mut stranger_data,
+            &SYSTEM_PROGRAM_ID,
+            false,
+            Epoch::default(),
+        );
+
+        let tier_90 = Tier { duration: 90, interest_numerator: 12, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: true, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 };
+        assert_eq!(
+            _process_add_tier_instruction(&program_id, &pool_config_account, &stranger_account, tier_90.clone()),
+            Err(StakingError::NotPoolAdmin.into())
+        );
+        _process_add_tier_instruction(&program_id, &pool_config_account, &admin_account, tier_90.clone()).unwrap();
+        assert_eq!(
+            _process_add_tier_instruction(&program_id, &pool_config_account, &admin_account, tier_90),
+            Err(StakingError::TierAlreadyExists.into())
+        );
+        let pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+        assert_eq!(
+            pool_config.tiers.iter().map(|tier| tier.duration).collect::<Vec<_>>(),
+            vec![90, 180, 360]
+        );
+
+        _process_update_tier_rate_instruction(&program_id, &pool_config_account, &admin_account, 180, 16, 100).unwrap();
+        assert_eq!(
+            _process_update_tier_rate_instruction(&program_id, &pool_config_account, &admin_account, 720, 16, 100),
+            Err(StakingError::TierNotFound.into())
+        );
+        assert_eq!(
+            _process_update_tier_rate_instruction(&program_id, &pool_config_account, &admin_account, 180, 16, 0),
+            Err(StakingError::InvalidTier.into())
+        );
+
+        _process_retire_tier_instruction(&program_id, &pool_config_account, &admin_account, 90).unwrap();
+        assert_eq!(
+            _process_retire_tier_instruction(&program_id, &pool_config_account, &admin_account, 90),
+            Err(StakingError::TierRetired.into())
+        );
+        assert_eq!(
+            _process_update_tier_rate_instruction(&program_id, &pool_config_account, &admin_account, 90, 20, 100),
+            Err(StakingError::TierRetired.into())
+        );
+
+        let pool_config = PoolConfig::load(&program_id, &pool_config_account).unwrap();
+        assert_eq!(pool_config.find_tier(90).unwrap().retired, true);
+        assert_eq!(pool_config.find_tier(180).unwrap().interest_numerator, 16);
+        assert_eq!(pool_config.find_tier(360).unwrap().interest_numerator, 17);
+    }
+
+    #[test]
+    fn test_unpacking_instructions() {
+        let data = [0, 3, 0xb4, 0x00, 5, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0];
+        let instruction = Instruction::unpack(&data).unwrap();
+        assert_eq!(instruction, Instruction::Stake{duration: 180, amount: 5, position_index: 7});
+        // Стейк в заранее созданный клиентом аккаунт больше не поддерживается.
+        let data = [0, 1, 0xb4, 0x00, 5, 0, 0, 0, 0, 0, 0, 0, 2];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        let data = [0, 2, 0xb4, 0x00, 5, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        // Старые клиенты еще присылают bump seed, его просто игнорируем.
+        assert_eq!(Instruction::unpack(&[1, 1, 2]).unwrap(), Instruction::WithdrawInterest);
+        assert_eq!(Instruction::unpack(&[3, 3]).unwrap(), Instruction::CloseAccount);
+        // Без получателя ренты старые версии CloseAccount не исполнить.
+        assert_eq!(Instruction::unpack(&[3, 2]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(Instruction::unpack(&[24, 1, 0x10, 0x27, 0, 0, 0, 0, 0, 0]).unwrap(), Instruction::FundRewards { amount: 10_000 });
+        assert_eq!(Instruction::unpack(&[25, 1, 1]).unwrap(), Instruction::SetInsolvencyPolicy { insolvency_policy: InsolvencyPolicy::FirstCome });
+        assert_eq!(Instruction::unpack(&[25, 1, 3]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(Instruction::unpack(&[26, 1, 2]).unwrap(), Instruction::SetPause { paused: PAUSE_INTEREST });
+        assert_eq!(Instruction::unpack(&[27, 1]).unwrap(), Instruction::EmergencyExit);
+    }
+
+    #[test]
+    fn test_unpacking_instructions_short_data() {
+        let data = [0, 3, 0xb4, 0x00, 5, 0, 0, 0 , 0, 0, 0];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        // Любой префикс корректной инструкции - ошибка, а не паника.
+        let data = Instruction::InitializePool { tiers: default_tiers() }.pack();
+        for len in 0..data.len() {
+            assert_eq!(Instruction::unpack(&data[..len]), Err(ProgramError::InvalidInstructionData));
+        }
+    }
+
+    #[test]
+    fn test_unpacking_instructions_bad_first_byte() {
+        let data = [0xff, 0xb4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 5, 0, 0, 0 , 0, 0, 0, 0];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_unpacking_instructions_bad_version_or_trailing_data() {
+        let data = [1, 0, 2];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        let data = [1, 2, 2];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        let data = [1, 3];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        let data = [1, 1, 2, 0];
+        assert_eq!(Instruction::unpack(&data), Err(ProgramError::InvalidInstructionData));
+        // Отброшенные версии Stake и CloseAccount.
+        assert_eq!(Instruction::unpack(&[0, 2, 180, 0, 5, 0, 0, 0, 0, 0, 0, 0]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(Instruction::unpack(&[3, 1, 255]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(Instruction::unpack(&[3, 2]), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_pack_unpack_round_trip() {
+        let instructions = vec![
+            Instruction::Stake { duration: 180, amount: u64::MAX, position_index: u64::MAX },
+            Instruction::WithdrawInterest,
+            Instruction::Compound,
+            Instruction::CloseAccount,
+            Instruction::InitializePool { tiers: default_tiers() },
+            Instruction::InitializePool { tiers: vec![] },
+            Instruction::AddTier { tier: Tier { duration: 720, interest_numerator: 21, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: true, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 } },
+            Instruction::UpdateTierRate { duration: 360, interest_numerator: 18, interest_denominator: 100 },
+            Instruction::RetireTier { duration: 180 },
+            Instruction::EarlyUnstake,
+            Instruction::SetTierPenalty { duration: 360, early_unstake_penalty_bps: 10_000, early_unstake_forfeits_interest: false },
+            Instruction::SetTreasury,
+            Instruction::AddStake { amount: u64::MAX },
+            Instruction::WithdrawPrincipal { amount: 1 },
+            Instruction::SetAutoRenew { auto_renew: true, compound: true },
+            Instruction::Renew { duration: 360, compound: false },
+            Instruction::CrankRenew,
+            Instruction::SetTierPostMaturity { duration: 360, post_maturity_accrual: PostMaturityAccrual::Flexible, flexible_interest_numerator: 1, flexible_interest_denominator: u64::MAX },
+            Instruction::TransferPosition { new_holder: Pubkey::new_unique(), new_holder_signs: false },
+            Instruction::SetDelegatedCompounding { enabled: false },
+            Instruction::SetKeeperTip { keeper_tip_bps: u16::MAX },
+            Instruction::CrankCompound,
+            Instruction::ClaimRewards,
+            Instruction::AddRewardStream { start: -1, end: i64::MAX, rates: vec![StreamRate { duration: 180, numerator: 1, denominator: 3 }] },
+            Instruction::RemoveRewardStream { id: u64::MAX },
+            Instruction::FundRewards { amount: u64::MAX },
+            Instruction::SetInsolvencyPolicy { insolvency_policy: InsolvencyPolicy::ProRata },
+            Instruction::SetPause { paused: PAUSE_ALL },
+            Instruction::EmergencyExit,
+        ];
+        for instruction in instructions {
+            assert_eq!(Instruction::unpack(&instruction.pack()).unwrap(), instruction);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "client")]
+    fn test_instruction_builders() {
+        let program_id = Pubkey::new_unique();
+        let staking = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let owner_token = Pubkey::new_unique();
+        let pool_token = Pubkey::new_unique();
+        let (pool_authority, _) = find_pool_authority_address(&program_id);
+        let (pool_config, _) = find_pool_config_address(&program_id);
+
+        let payer = Pubkey::new_unique();
+        let (position, _) = find_staking_account_address(&program_id, &owner, 3);
+        let ix = instruction::stake(&program_id, &payer, &owner, &owner_token, &pool_token, 360, 12, 3);
+        assert_eq!(ix.program_id, program_id);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::Stake { duration: 360, amount: 12, position_index: 3 });
+        assert_eq!(
+            ix.accounts.iter().map(|meta| (meta.pubkey, meta.is_writable, meta.is_signer)).collect::<Vec<_>>(),
+            vec![
+                (position, true, false),
+                (owner, false, true),
+                (owner_token, true, false),
+                (pool_token, true, false),
+                (SPL_TOKEN_PROGRAM_ID, false, false),
+                (pool_config, true, false),
+                (payer, true, true),
+                (SYSTEM_PROGRAM_ID, false, false),
+            ]
+        );
+        // Позиции разных холдеров и с разными номерами не пересекаются.
+        assert_ne!(position, find_staking_account_address(&program_id, &owner, 4).0);
+        assert_ne!(position, find_staking_account_address(&program_id, &payer, 3).0);
+
+        let ix = instruction::close_account(&program_id, &staking, &owner, &owner_token, &pool_token, &payer);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::CloseAccount);
+        assert_eq!(ix.accounts[5].pubkey, pool_authority);
+        assert_eq!((ix.accounts[6].pubkey, ix.accounts[6].is_writable), (pool_config, true));
+        assert_eq!((ix.accounts[7].pubkey, ix.accounts[7].is_writable), (payer, true));
+
+        let reward_vault = Pubkey::new_unique();
+        let owner_reward_token = Pubkey::new_unique();
+        let mut ix = instruction::withdraw_interest(&program_id, &staking, &owner, &owner_token, &pool_token);
+        instruction::add_reward_accounts(&mut ix, &reward_vault, &owner_reward_token);
+        assert_eq!(ix.accounts.len(), 9);
+        assert_eq!((ix.accounts[7].pubkey, ix.accounts[7].is_writable), (reward_vault, true));
+        assert_eq!((ix.accounts[8].pubkey, ix.accounts[8].is_writable), (owner_reward_token, true));
+
+        let stream_vault = Pubkey::new_unique();
+        let owner_stream_token = Pubkey::new_unique();
+        let ix = instruction::claim_rewards(&program_id, &staking, &owner, &[(stream_vault, owner_stream_token)]);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::ClaimRewards);
+        assert_eq!(ix.accounts[3].pubkey, pool_authority);
+        assert_eq!(ix.accounts[4].pubkey, pool_config);
+        assert_eq!((ix.accounts[5].pubkey, ix.accounts[6].pubkey), (stream_vault, owner_stream_token));
+
+        let keeper_token = Pubkey::new_unique();
+        let ix = instruction::crank_compound(&program_id, &staking, &pool_token, &keeper_token);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::CrankCompound);
+        assert!(ix.accounts.iter().all(|meta| !meta.is_signer));
+        assert_eq!(ix.accounts[3].pubkey, pool_authority);
+        assert_eq!((ix.accounts[5].pubkey, ix.accounts[5].is_writable), (keeper_token, true));
+
+        // Крэнк не требует подписи холдера.
+        let ix = instruction::crank_renew(&program_id, &staking);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::CrankRenew);
+        assert!(ix.accounts.iter().all(|meta| !meta.is_signer));
+        assert_eq!((ix.accounts[0].pubkey, ix.accounts[0].is_writable), (staking, true));
+        assert_eq!(ix.accounts[1].pubkey, pool_config);
+
+        let ix = instruction::emergency_exit(&program_id, &staking, &owner, &owner_token, &pool_token, &payer);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::EmergencyExit);
+        assert_eq!(ix.accounts.len(), 8);
+        assert_eq!((ix.accounts[7].pubkey, ix.accounts[7].is_writable), (payer, true));
+
+        let funder = Pubkey::new_unique();
+        let ix = instruction::fund_rewards(&program_id, &funder, &owner_token, &pool_token, 500);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::FundRewards { amount: 500 });
+        assert_eq!(
+            ix.accounts.iter().map(|meta| (meta.pubkey, meta.is_writable, meta.is_signer)).collect::<Vec<_>>(),
+            vec![
+                (pool_config, true, false),
+                (funder, false, true),
+                (owner_token, true, false),
+                (pool_token, true, false),
+                (SPL_TOKEN_PROGRAM_ID, false, false),
+            ]
+        );
+
+        let admin = Pubkey::new_unique();
+        let ix = instruction::retire_tier(&program_id, &admin, 180);
+        assert_eq!(Instruction::unpack(&ix.data).unwrap(), Instruction::RetireTier { duration: 180 });
+        assert!(ix.accounts[0].is_writable);
+        assert!(ix.accounts[1].is_signer);
+    }
+
+    #[test]
+    fn test_interest_on_unlocked_account() {
+
+    }
+
+    #[test]
+    fn test_unstaking_wrong_owner() {
+
+    }
+
+
+    #[test]
+    fn test_no_interest_on_closed_account() {
+        let program_id = Pubkey::new_unique();
         let owner = Pubkey::new_unique();
 
         let staking_account_key = Pubkey::new_unique();
-        let mut staking_account_lamports = 0;
+        let mut staking_account_lamports = 1_000;
         let mut staking_account_data = vec![0; STAKING_ACCOUNT_SIZE];
-        let mut staking_account = AccountInfo::new(
+        let staking_account = AccountInfo::new(
             &staking_account_key,
             false,
             true,
@@ -624,7 +6348,7 @@ mod test {
 
         let owners_token_account_key = Pubkey::new_unique();
         let mut owners_token_account_lamports = 0;
-        let mut owners_token_account_data = vec![0; 0];
+        let mut owners_token_account_data = token_account_data(&TEST_MINT, &owner, 1_000_000);
         let owners_token_account = AccountInfo::new(
             &owners_token_account_key,
             false,
@@ -636,11 +6360,11 @@ mod test {
             Epoch::default(),
         );
 
-        let (pools_token_account_key, bump_seed) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
+        let (pool_owner, _) = Pubkey::find_program_address(&[POOL_ADDRESS_SEED], &program_id);
         let mut pools_token_account_lamports = 0;
-        let mut pools_token_account_data = vec![0; 0];
+        let mut pools_token_account_data = token_account_data(&TEST_MINT, &pool_owner, 0);
         let pools_token_account = AccountInfo::new(
-            &pools_token_account_key,
+            &TEST_VAULT,
             false,
             true,
             &mut pools_token_account_lamports,
@@ -655,7 +6379,7 @@ mod test {
         let owners_account = AccountInfo::new(
             &owner,
             true,
-            false,
+            true,
             &mut owners_account_lamports,
             &mut owners_account_data,
             &SYSTEM_PROGRAM_ID,
@@ -663,53 +6387,80 @@ mod test {
             Epoch::default(),
         );
 
-        let result = _process_staking_instruction(
-            &program_id, 
-            &mut staking_account,
+        let (pool_config_key, mut pool_config_data) = pool_config_data(&program_id, &Pubkey::new_unique());
+        let mut pool_config_lamports = 0;
+        let pool_config_account = AccountInfo::new(
+            &pool_config_key,
+            false,
+            false,
+            &mut pool_config_lamports,
+            &mut pool_config_data,
+            &program_id,
+            false,
+            Epoch::default(),
+        );
+
+        let created = 1234567890 as UnixTimestamp;
+        _process_staking_instruction(
+            &program_id,
+            &staking_account,
             &owners_account,
             &owners_token_account,
             &pools_token_account,
-            12u64,
-            1234567890 as UnixTimestamp,
-            45u16,
-            bump_seed);
-        assert_eq!(result.is_ok(), false);
-    }
-
-    #[test]
-    fn test_unpacking_instructions() {
-        let data = [0, 0xb4, 0x00, 5, 0, 0, 0, 0, 0, 0, 0, 2];
-        let instruction = Instruction::unpack(&data).unwrap();
-        assert_eq!(instruction, Instruction::Stake{duration: 180, amount: 5, bump_seed: 2})
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_unpacking_instructions_short_data() {
-        let data = [0, 0xb4, 0x00, 5, 0, 0, 0 , 0, 0, 0];
-        Instruction::unpack(&data);
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_unpacking_instructions_bad_first_byte() {
-        let data = [0xff, 0xb4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 5, 0, 0, 0 , 0, 0, 0, 0];
-        Instruction::unpack(&data);
-    }
-
-    #[test]
-    fn test_interest_on_unlocked_account() {
-
-    }
+            &pool_config_account,
+            1_000_000u64,
+            created,
+            180u16).unwrap();
 
-    #[test]
-    fn test_unstaking_wrong_owner() {
-
-    }
+        let withdraw = |now: UnixTimestamp, withdraw_strategy: WithdrawStrategy| {
+            _process_withdraw_interest_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                now,
+                withdraw_strategy).and_then(|payout| payout.total())
+        };
+        assert_eq!(
+            withdraw(created + 90 * 24 * 3600, WithdrawStrategy::CloseAccount),
+            Err(StakingError::LockNotExpired.into())
+        );
+        // Тело и проценты за 180 дней под 15% годовых.
+        let closed_at = created + 180 * 24 * 3600;
+        assert_eq!(withdraw(closed_at, WithdrawStrategy::CloseAccount), Ok(1_075_000u64));
 
+        let staking_info = StakingAccount::try_from_slice(&staking_account.data.borrow()).unwrap();
+        assert_eq!(staking_info.closed, true);
+        assert_eq!(staking_info.initialized, false);
+        assert_eq!(staking_info.holder, Pubkey::default());
+        assert_eq!(staking_info.token_amount, 0u64);
 
-    #[test]
-    fn test_no_interest_on_closed_account() {
+        assert_eq!(_close_staking_account(&staking_account, &staking_account), Err(ProgramError::InvalidArgument));
+        _close_staking_account(&staking_account, &owners_account).unwrap();
+        assert_eq!(staking_account.lamports(), 0);
+        assert_eq!(owners_account.lamports(), 1_000);
 
+        // Повторное закрытие или снятие процентов ничего не выплачивает.
+        for withdraw_strategy in [WithdrawStrategy::CloseAccount, WithdrawStrategy::InterestOnly, WithdrawStrategy::Compound] {
+            assert_eq!(
+                withdraw(closed_at + SECONDS_PER_YEAR as UnixTimestamp, withdraw_strategy),
+                Err(StakingError::PositionClosed.into())
+            );
+        }
+        assert_eq!(
+            _process_staking_instruction(
+                &program_id,
+                &staking_account,
+                &owners_account,
+                &owners_token_account,
+                &pools_token_account,
+                &pool_config_account,
+                1_000_000u64,
+                closed_at,
+                180u16),
+            Err(StakingError::PositionClosed.into())
+        );
     }
 }
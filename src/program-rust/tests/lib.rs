@@ -1,20 +1,257 @@
-use borsh::BorshDeserialize;
-use helloworld::{process_instruction, GreetingAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use helloworld::{
+    cpi, error::StakingError, find_pool_authority_address, find_pool_config_address, find_staking_account_address, instruction,
+    process_instruction, InsolvencyPolicy, PoolConfig, PostMaturityAccrual, RewardStream, StakingAccount, Tier, MAX_REWARD_STREAMS,
+    POOL_CONFIG_SIZE, STAKING_ACCOUNT_SIZE,
+};
 use solana_program_test::*;
 use solana_sdk::{
     account::Account,
-    instruction::{AccountMeta, Instruction},
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
     pubkey::Pubkey,
     signer::keypair::Keypair,
     signature::Signer,
-    transaction::Transaction,
-};
-use spl_token::{
-    ID as SPL_TOKEN_PROGRAM_ID
+    transaction::{Transaction, TransactionError},
 };
-use std::mem;
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
 use core::convert::TryInto;
 
+const VAULT_SEED: &[u8] = b"vault";
+
+// Программа-хранилище, которая стейкает от имени своего PDA.
+fn vault_process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let staking_program = next_account_info(account_info_iter)?;
+    let staking_account = next_account_info(account_info_iter)?;
+    let vault_authority = next_account_info(account_info_iter)?;
+    let vault_token_account = next_account_info(account_info_iter)?;
+    let pool_token_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let pool_config = next_account_info(account_info_iter)?;
+    let payer = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let amount = u64::from_le_bytes(instruction_data.try_into().unwrap());
+    let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], program_id);
+    cpi::stake(
+        staking_program,
+        staking_account,
+        vault_authority,
+        vault_token_account,
+        pool_token_account,
+        token_program,
+        pool_config,
+        payer,
+        system_program,
+        360,
+        amount,
+        0,
+        &[&[VAULT_SEED, &[bump]]],
+    )
+}
+
+fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+    let mut data = vec![0u8; TokenAccount::LEN];
+    TokenAccount {
+        mint,
+        owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    }.pack_into_slice(&mut data);
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: spl_token::id(),
+        ..Account::default()
+    }
+}
+
+fn default_tiers() -> Vec<Tier> {
+    vec![
+        Tier { duration: 180, interest_numerator: 15, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: true, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 },
+        Tier { duration: 360, interest_numerator: 17, interest_denominator: 100, retired: false, early_unstake_penalty_bps: 0, early_unstake_forfeits_interest: true, post_maturity_accrual: PostMaturityAccrual::FullRate, flexible_interest_numerator: 0, flexible_interest_denominator: 0 },
+    ]
+}
+
+fn pool_config_account(program_id: &Pubkey, stake_mint: Pubkey, vault: Pubkey) -> Account {
+    let (_, authority_bump) = find_pool_authority_address(program_id);
+    let (_, bump) = find_pool_config_address(program_id);
+    let mut data = vec![0u8; POOL_CONFIG_SIZE];
+    PoolConfig {
+        initialized: true,
+        admin: Pubkey::new_unique(),
+        stake_mint,
+        vault,
+        bump,
+        authority_bump,
+        treasury: Pubkey::default(),
+        keeper_tip_bps: 0,
+        reward_mint: Pubkey::default(),
+        reward_vault: Pubkey::default(),
+        total_principal: 0,
+        // Резерв как будто уже пополнен через FundRewards, иначе стейк не пройдет.
+        reward_reserve: 1_000_000,
+        interest_liability: 0,
+        insolvency_policy: InsolvencyPolicy::Revert,
+        paused: 0,
+        last_reward_stream_id: 0,
+        reward_streams: [RewardStream::default(); MAX_REWARD_STREAMS],
+        tiers: default_tiers(),
+    }.serialize(&mut &mut data[..]).unwrap();
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: *program_id,
+        ..Account::default()
+    }
+}
+
+// Нативный processor в solana-program-test 1.8 не умеет менять размер данных аккаунта в CPI,
+// поэтому позицию кладем заранее. Под `cargo test-bpf` ее создает сам Stake.
+fn add_position_account_for_native_run(program_test: &mut ProgramTest, program_id: &Pubkey, staking_account: Pubkey) {
+    if cfg!(feature = "test-bpf") {
+        return;
+    }
+    program_test.add_account(
+        staking_account,
+        Account { lamports: 1_000_000_000, data: vec![0u8; STAKING_ACCOUNT_SIZE], owner: *program_id, ..Account::default() },
+    );
+}
+
+// Пул с одним холдером, у которого 1000 токенов.
+fn stake_program_test(program_id: &Pubkey, owner: &Pubkey) -> (ProgramTest, Pubkey, Pubkey) {
+    let mut program_test = ProgramTest::new("helloworld", *program_id, processor!(process_instruction));
+    let (pool_manager, _) = find_pool_authority_address(program_id);
+    let (pool_config, _) = find_pool_config_address(program_id);
+
+    let mint = Pubkey::new_unique();
+    let mut mint_data = vec![0u8; Mint::LEN];
+    Mint { decimals: 9, is_initialized: true, supply: 1000, ..Mint::default() }.pack_into_slice(&mut mint_data);
+    program_test.add_account(mint, Account { lamports: 1_000_000_000, data: mint_data, owner: spl_token::id(), ..Account::default() });
+
+    let owner_token_account = Pubkey::new_unique();
+    program_test.add_account(owner_token_account, token_account(mint, *owner, 1000));
+    let pool_token_account = Pubkey::new_unique();
+    program_test.add_account(pool_token_account, token_account(mint, pool_manager, 0));
+    program_test.add_account(pool_config, pool_config_account(program_id, mint, pool_token_account));
+    (program_test, owner_token_account, pool_token_account)
+}
+
+#[tokio::test]
+async fn test_stake_wrong_position_address() {
+    let program_id = Pubkey::new_unique();
+    let owner = Keypair::new();
+    let (program_test, owner_token_account, pool_token_account) = stake_program_test(&program_id, &owner.pubkey());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    // Позиция не по PDA холдера и индекса.
+    let mut ix = instruction::stake(&program_id, &payer.pubkey(), &owner.pubkey(), &owner_token_account, &pool_token_account, 360, 400, 0);
+    ix.accounts[0].pubkey = find_staking_account_address(&program_id, &owner.pubkey(), 1).0;
+    let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(StakingError::InvalidStakingAccountAddress as u32))
+    );
+}
+
+#[cfg(feature = "test-bpf")]
+#[tokio::test]
+async fn test_stake_creates_position_account() {
+    let program_id = Pubkey::new_unique();
+    let owner = Keypair::new();
+    let (mut program_test, owner_token_account, pool_token_account) = stake_program_test(&program_id, &owner.pubkey());
+    // На адрес второй позиции кто-то заранее перевел лампорты, create_account на нем бы упал.
+    let (prefunded_staking_account, _) = find_staking_account_address(&program_id, &owner.pubkey(), 1);
+    program_test.add_account(
+        prefunded_staking_account,
+        Account { lamports: 1_000, owner: solana_sdk::system_program::id(), ..Account::default() },
+    );
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::stake(&program_id, &payer.pubkey(), &owner.pubkey(), &owner_token_account, &pool_token_account, 360, 400, 0),
+            instruction::stake(&program_id, &payer.pubkey(), &owner.pubkey(), &owner_token_account, &pool_token_account, 360, 100, 1),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    for (position_index, token_amount) in [(0, 400), (1, 100)] {
+        let (staking_account, _) = find_staking_account_address(&program_id, &owner.pubkey(), position_index);
+        let account = banks_client.get_account(staking_account).await.unwrap().unwrap();
+        assert_eq!(account.owner, program_id);
+        assert_eq!(account.data.len(), STAKING_ACCOUNT_SIZE);
+        let staking_info = StakingAccount::try_from_slice(&account.data).unwrap();
+        assert_eq!(staking_info.holder, owner.pubkey());
+        assert_eq!(staking_info.token_amount, token_amount);
+    }
+}
+
+#[tokio::test]
+async fn test_stake_via_cpi() {
+    let program_id = Pubkey::new_unique();
+    let vault_program_id = Pubkey::new_unique();
+    let mut program_test = ProgramTest::new("helloworld", program_id, processor!(process_instruction));
+    program_test.add_program("vault", vault_program_id, processor!(vault_process_instruction));
+
+    let (vault_authority, _) = Pubkey::find_program_address(&[VAULT_SEED], &vault_program_id);
+    let (pool_manager, _) = find_pool_authority_address(&program_id);
+    let (pool_config, _) = find_pool_config_address(&program_id);
+
+    let mint = Pubkey::new_unique();
+    let mut mint_data = vec![0u8; Mint::LEN];
+    Mint { decimals: 9, is_initialized: true, supply: 1000, ..Mint::default() }.pack_into_slice(&mut mint_data);
+    program_test.add_account(mint, Account { lamports: 1_000_000_000, data: mint_data, owner: spl_token::id(), ..Account::default() });
+
+    let vault_token_account = Pubkey::new_unique();
+    program_test.add_account(vault_token_account, token_account(mint, vault_authority, 1000));
+    let pool_token_account = Pubkey::new_unique();
+    program_test.add_account(pool_token_account, token_account(mint, pool_manager, 0));
+    program_test.add_account(pool_config, pool_config_account(&program_id, mint, pool_token_account));
+    let (staking_account, _) = find_staking_account_address(&program_id, &vault_authority, 0);
+    add_position_account_for_native_run(&mut program_test, &program_id, staking_account);
+
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let mut transaction = Transaction::new_with_payer(
+        &[Instruction::new_with_bytes(
+            vault_program_id,
+            &400u64.to_le_bytes(),
+            vec![
+                AccountMeta::new_readonly(program_id, false),
+                AccountMeta::new(staking_account, false),
+                AccountMeta::new_readonly(vault_authority, false),
+                AccountMeta::new(vault_token_account, false),
+                AccountMeta::new(pool_token_account, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(pool_config, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
+            ],
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    let staking_info = StakingAccount::try_from_slice(
+        &banks_client.get_account(staking_account).await.unwrap().unwrap().data
+    ).unwrap();
+    assert_eq!(staking_info.holder, vault_authority);
+    assert_eq!(staking_info.token_amount, 400);
+    let pool_tokens = TokenAccount::unpack(&banks_client.get_account(pool_token_account).await.unwrap().unwrap().data).unwrap();
+    assert_eq!(pool_tokens.amount, 400);
+}
+
 #[tokio::test]
 async fn test_staking() {
 
@@ -37,7 +274,8 @@ async fn test_helloworld() {
 
     // spl-token mint 6e13iFuJrkFVF1WsrDZ1ux5Ni5RTWKc6Br6cZTzsimVB 1000 GfmS5GvqiH1HFi1xVLmdMjTv4eRpp4Qyg79wPFQcDmwE -ul
     let pool_token_account = Pubkey::new_unique();
-    let (pool_manager_account, bump_seed) = Pubkey::find_program_address(&[&[0x50, 0x00, 0x00, 0x10, 0x20, 0xad, 0x35]], &program_id);
+    let (pool_manager_account, _) = find_pool_authority_address(&program_id);
+    let (pool_config, _) = find_pool_config_address(&program_id);
 
     // TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA
     let token_program = Pubkey::new(&[
@@ -89,31 +327,13 @@ async fn test_helloworld() {
         }
     );
 
-    let staking_account_id = Pubkey::new_unique();
-    program_test.add_account(
-        staking_account_id,
-        Account {
-            lamports: 1,
-            data: [0u8;1 + 32 + 8 + 2 + 8 + 8 + 8].to_vec(),
-            owner: program_id,
-            ..Account::default()
-        }
-    );
+    // Конфиг пула кладем готовым, как будто InitializePool уже выполнен upgrade authority программы.
+    program_test.add_account(pool_config, pool_config_account(&program_id, token_mint_account, pool_token_account));
 
     let owner = Keypair::new();
 
-    let old_staking_account_id = Pubkey::new_unique();
-    let mut old_staking_account_data = vec![0x01u8];
-    old_staking_account_data.extend_from_slice(&owner.pubkey().to_bytes());
-    program_test.add_account(
-        old_staking_account_id,
-        Account {
-            lamports: 1,
-            data: old_staking_account_data,
-            owner: program_id,
-            ..Account::default()
-        }
-    );
+    let (staking_account_id, _) = find_staking_account_address(&program_id, &owner.pubkey(), 0);
+    add_position_account_for_native_run(&mut program_test, &program_id, staking_account_id);
 
     let mut owners_token_account_data = vec![];
     owners_token_account_data.extend_from_slice(&token_mint_account.to_bytes());
@@ -140,22 +360,16 @@ async fn test_helloworld() {
     let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
 
     // Stake
-    let instruction_data: [u8;12] = [
-        0x00,
-        0xb4, 0x00,
-        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-        bump_seed];
     let mut transaction = Transaction::new_with_payer(
-        &[Instruction::new_with_bincode(
-            program_id,
-            &instruction_data,
-            vec![
-                AccountMeta::new(staking_account_id, false),
-                AccountMeta::new(owner.pubkey(), true),
-                AccountMeta::new(owners_token_account, false),
-                AccountMeta::new(pool_token_account, false),
-                AccountMeta::new_readonly(token_program, false),
-            ],
+        &[instruction::stake(
+            &program_id,
+            &payer.pubkey(),
+            &owner.pubkey(),
+            &owners_token_account,
+            &pool_token_account,
+            180,
+            5,
+            0,
         )],
         Some(&payer.pubkey()),
     );
@@ -172,21 +386,13 @@ async fn test_helloworld() {
     let amount_after_staking = u64::from_le_bytes(pool_token_account_after_staking.data.get(64..72).unwrap().try_into().unwrap());
     assert_eq!(18374686479671623685u64, amount_after_staking);
 
-    let instruction_data: [u8;2] = [
-        0x01,
-        bump_seed];
     let mut transaction = Transaction::new_with_payer(
-        &[Instruction::new_with_bincode(
-            program_id,
-            &instruction_data,
-            vec![
-                AccountMeta::new(staking_account_id, false),
-                AccountMeta::new(owner.pubkey(), true),
-                AccountMeta::new(owners_token_account, false),
-                AccountMeta::new(pool_token_account, false),
-                AccountMeta::new_readonly(token_program, false),
-                AccountMeta::new_readonly(pool_manager_account, false),
-            ],
+        &[instruction::withdraw_interest(
+            &program_id,
+            &staking_account_id,
+            &owner.pubkey(),
+            &owners_token_account,
+            &pool_token_account,
         )],
         Some(&payer.pubkey()),
     );